@@ -0,0 +1,331 @@
+use std::path::Path;
+
+use anyhow::Context;
+use rorm_db::executor::Executor;
+use rorm_declaration::imr::{Annotation, DbType, Field, Model};
+use rorm_declaration::migration::Migration;
+use rorm_sql::DBImpl;
+
+use crate::migrate::config::deserialize_db_conf;
+use crate::migrate::{
+    applied_migration_ids, connect_database, get_applied_migrations_read_only,
+    last_migration_table_name,
+};
+use crate::print_message;
+use crate::utils::introspection::{introspect_database, DbColumn, DbTable};
+use crate::utils::migrations::{
+    convert_migrations_to_internal_models, get_all_existing_migrations, get_existing_migrations,
+    substitute_squashed_migrations,
+};
+use crate::utils::output::{report, Event, UnexpectedChanges};
+
+/// Coarse classification of data types to compare types across databases
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TypeFamily {
+    Integer,
+    Float,
+    Text,
+    Binary,
+    Boolean,
+    Date,
+    DateTime,
+    Time,
+    /// Types that can not be classified are never reported
+    Other,
+}
+
+impl TypeFamily {
+    fn of_db_type(db_type: DbType) -> Self {
+        match db_type {
+            DbType::VarChar | DbType::Choices => Self::Text,
+            DbType::Binary => Self::Binary,
+            DbType::Int8 | DbType::Int16 | DbType::Int32 | DbType::Int64 => Self::Integer,
+            DbType::Float | DbType::Double => Self::Float,
+            DbType::Boolean => Self::Boolean,
+            DbType::Date => Self::Date,
+            DbType::DateTime | DbType::Timestamp => Self::DateTime,
+            DbType::Time => Self::Time,
+            #[allow(unreachable_patterns)]
+            _ => Self::Other,
+        }
+    }
+
+    fn of_data_type(data_type: &str) -> Self {
+        let data_type = data_type.to_lowercase();
+
+        if data_type.contains("int") || data_type.contains("serial") {
+            Self::Integer
+        } else if ["real", "float", "double", "numeric", "decimal"]
+            .iter()
+            .any(|x| data_type.contains(x))
+        {
+            Self::Float
+        } else if ["char", "text", "clob", "enum", "user-defined"]
+            .iter()
+            .any(|x| data_type.contains(x))
+        {
+            Self::Text
+        } else if ["blob", "bytea", "binary"]
+            .iter()
+            .any(|x| data_type.contains(x))
+        {
+            Self::Binary
+        } else if data_type.contains("bool") {
+            Self::Boolean
+        } else if data_type.contains("timestamp") || data_type.contains("datetime") {
+            Self::DateTime
+        } else if data_type.starts_with("date") {
+            Self::Date
+        } else if data_type.starts_with("time") {
+            Self::Time
+        } else {
+            Self::Other
+        }
+    }
+
+    /// Checks whether a column of family `actual` can hold values of family `self`
+    fn is_compatible(self, actual: Self, dialect: DBImpl) -> bool {
+        let sqlite = match dialect {
+            #[cfg(feature = "sqlite")]
+            DBImpl::SQLite => true,
+            #[allow(unreachable_patterns)]
+            _ => false,
+        };
+
+        self == actual
+            || self == Self::Other
+            || actual == Self::Other
+            // Booleans are stored as integers by MySQL and SQLite
+            || (self == Self::Boolean && actual == Self::Integer)
+            // SQLite has no dedicated date and time types
+            || (sqlite
+                && actual == Self::Text
+                && matches!(self, Self::Date | Self::DateTime | Self::Time))
+    }
+}
+
+/// Compares a field of a model with the column found in the database
+fn compare_column(
+    model: &str,
+    field: &Field,
+    column: &DbColumn,
+    dialect: DBImpl,
+    differences: &mut Vec<String>,
+) {
+    let expected = TypeFamily::of_db_type(field.db_type);
+    if !expected.is_compatible(TypeFamily::of_data_type(&column.data_type), dialect) {
+        differences.push(format!(
+            "Column {}.{} has type {}, expected {:?}",
+            model, field.name, column.data_type, field.db_type
+        ));
+    }
+
+    let primary_key = field.annotations.contains(&Annotation::PrimaryKey);
+    let not_null = primary_key || field.annotations.contains(&Annotation::NotNull);
+    let unique = field.annotations.contains(&Annotation::Unique);
+
+    if primary_key != column.primary_key {
+        differences.push(format!(
+            "Column {}.{} is {}part of the primary key in the database",
+            model,
+            field.name,
+            if column.primary_key { "" } else { "not " },
+        ));
+    }
+
+    if not_null == column.nullable {
+        differences.push(format!(
+            "Column {}.{} is {} in the database",
+            model,
+            field.name,
+            if column.nullable {
+                "nullable"
+            } else {
+                "not nullable"
+            },
+        ));
+    }
+
+    if !primary_key && unique != column.unique {
+        differences.push(format!(
+            "Column {}.{} is {}unique in the database",
+            model,
+            field.name,
+            if column.unique { "" } else { "not " },
+        ));
+    }
+
+    for annotation in &field.annotations {
+        if let (Annotation::MaxLength(max_length), Some(actual)) = (annotation, column.max_length) {
+            if *max_length as i64 != actual {
+                differences.push(format!(
+                    "Column {}.{} has a maximum length of {} in the database, expected {}",
+                    model, field.name, actual, max_length
+                ));
+            }
+        }
+    }
+
+    let foreign_key = field.annotations.iter().find_map(|x| match x {
+        Annotation::ForeignKey(fk) => Some((fk.table_name.as_str(), fk.column_name.as_str())),
+        _ => None,
+    });
+    let actual_foreign_key = column
+        .foreign_key
+        .as_ref()
+        .map(|(table, column)| (table.as_str(), column.as_str()));
+
+    match (foreign_key, actual_foreign_key) {
+        (Some((table, column)), None) => differences.push(format!(
+            "Column {}.{} misses the foreign key to {table}.{column} in the database",
+            model, field.name
+        )),
+        (None, Some((table, column))) => differences.push(format!(
+            "Column {}.{} references {table}.{column} in the database, but no foreign key is expected",
+            model, field.name
+        )),
+        // SQLite omits the referenced column if it is the primary key
+        (Some(expected), Some(actual))
+            if expected.0 != actual.0 || (!actual.1.is_empty() && expected.1 != actual.1) =>
+        {
+            differences.push(format!(
+                "Column {}.{} references {}.{} in the database, expected {}.{}",
+                model, field.name, actual.0, actual.1, expected.0, expected.1
+            ))
+        }
+        _ => {}
+    }
+}
+
+/// Compares the models with the tables found in the database
+///
+/// Returns a description of every difference.
+fn compare_schema(models: &[Model], tables: &[DbTable], dialect: DBImpl) -> Vec<String> {
+    let mut differences = vec![];
+
+    for model in models {
+        let Some(table) = tables.iter().find(|x| x.name == model.name) else {
+            differences.push(format!("Table {} is missing in the database", model.name));
+            continue;
+        };
+
+        for field in &model.fields {
+            match table.columns.iter().find(|x| x.name == field.name) {
+                None => differences.push(format!(
+                    "Column {}.{} is missing in the database",
+                    model.name, field.name
+                )),
+                Some(column) => {
+                    compare_column(&model.name, field, column, dialect, &mut differences)
+                }
+            }
+        }
+
+        for column in &table.columns {
+            if !model.fields.iter().any(|x| x.name == column.name) {
+                differences.push(format!(
+                    "Column {}.{} exists in the database, but not in the migrations",
+                    model.name, column.name
+                ));
+            }
+        }
+    }
+
+    for table in tables {
+        if !models.iter().any(|x| x.name == table.name) {
+            differences.push(format!(
+                "Table {} exists in the database, but not in the migrations",
+                table.name
+            ));
+        }
+    }
+
+    differences
+}
+
+/**
+Compares the schema of the database with the state of the applied migrations.
+
+Differences are reported in both directions, an error is returned if any difference is found.
+*/
+pub async fn check_db(
+    migration_dir: String,
+    database_config: String,
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    let p = Path::new(migration_dir.as_str());
+    if !p.exists() || p.is_file() {
+        print_message!(
+            "Couldn't find the migration directory in {} \n\n\
+            You can specify an alternative path with --migration-dir <PATH>",
+            migration_dir.as_str()
+        );
+        return Ok(());
+    }
+
+    let db_conf = deserialize_db_conf(Path::new(database_config.as_str()))?;
+
+    let existing_migrations = get_existing_migrations(migration_dir.as_str())
+        .with_context(|| "Couldn't retrieve existing migrations")?;
+
+    let pool = connect_database(&db_conf).await?;
+    let last_migration_table_name = last_migration_table_name(&db_conf);
+
+    let applied =
+        get_applied_migrations_read_only(&pool, last_migration_table_name, log_sql).await?;
+    let applied_ids = applied_migration_ids(
+        &applied,
+        &get_all_existing_migrations(migration_dir.as_str())?,
+    );
+
+    let last_applied = existing_migrations
+        .iter()
+        .rev()
+        .find(|x| applied_ids.contains(&x.id))
+        .map(|x| x.id);
+    let existing_migrations = substitute_squashed_migrations(
+        migration_dir.as_str(),
+        existing_migrations,
+        last_applied,
+        None,
+    )
+    .with_context(|| "Couldn't retrieve squashed migrations")?;
+
+    // Only the applied migrations are expected to be reflected in the database
+    let applied_migrations: Vec<Migration> = existing_migrations
+        .into_iter()
+        .filter(|x| applied_ids.contains(&x.id))
+        .collect();
+
+    let internal_models = convert_migrations_to_internal_models(&applied_migrations)
+        .with_context(|| "Error while parsing existing migration files")?;
+
+    let tables: Vec<DbTable> = introspect_database(&pool, log_sql)
+        .await?
+        .into_iter()
+        .filter(|x| x.name != last_migration_table_name)
+        .collect();
+
+    let differences = compare_schema(&internal_models.models, &tables, pool.dialect());
+
+    if differences.is_empty() {
+        let message = "The database matches the applied migrations.";
+        report(Event::NothingToDo { message }, message);
+        return Ok(());
+    }
+
+    for difference in &differences {
+        report(
+            Event::SchemaDifference {
+                message: difference,
+            },
+            difference,
+        );
+    }
+
+    Err(UnexpectedChanges(format!(
+        "Found {} differences between the database and the applied migrations",
+        differences.len()
+    ))
+    .into())
+}
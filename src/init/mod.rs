@@ -7,12 +7,14 @@ use rorm_declaration::config::{DatabaseConfig, DatabaseDriver};
 
 use crate::entry::InitDriver;
 use crate::migrate::config::DatabaseConfigFile;
+use crate::print_message;
+use crate::utils::output::{report, Event};
 
 /// Create the database configuration file
 pub fn init(database_configuration: String, driver: InitDriver, force: bool) -> anyhow::Result<()> {
     let p = Path::new(&database_configuration);
     if p.exists() && !force {
-        println!("Database configuration at {} does already exists. Use --force to overwrite the existing file.", &database_configuration);
+        print_message!("Database configuration at {} does already exists. Use --force to overwrite the existing file.", &database_configuration);
         exit(1);
     }
 
@@ -31,7 +33,12 @@ pub fn init(database_configuration: String, driver: InitDriver, force: bool) ->
             let mut f = File::create(p)?;
             write!(f, "{}", &serialized)?;
 
-            println!("Configuration was written to {}.", &database_configuration);
+            report(
+                Event::FileWritten {
+                    path: &database_configuration,
+                },
+                format!("Configuration was written to {}.", &database_configuration),
+            );
         }
         #[cfg(feature = "mysql")]
         InitDriver::Mysql {
@@ -66,7 +73,12 @@ pub fn init(database_configuration: String, driver: InitDriver, force: bool) ->
             let mut f = File::create(p)?;
             write!(f, "{}", &serialized)?;
 
-            println!("Configuration was written to {}.", &database_configuration);
+            report(
+                Event::FileWritten {
+                    path: &database_configuration,
+                },
+                format!("Configuration was written to {}.", &database_configuration),
+            );
         }
         #[cfg(feature = "postgres")]
         InitDriver::Postgres {
@@ -101,7 +113,12 @@ pub fn init(database_configuration: String, driver: InitDriver, force: bool) ->
             let mut f = File::create(p)?;
             write!(f, "{}", &serialized)?;
 
-            println!("Configuration was written to {}.", &database_configuration);
+            report(
+                Event::FileWritten {
+                    path: &database_configuration,
+                },
+                format!("Configuration was written to {}.", &database_configuration),
+            );
         }
     };
 
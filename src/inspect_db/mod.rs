@@ -0,0 +1,272 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{create_dir_all, remove_file};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use rorm_db::executor::{Executor, Nothing};
+use rorm_declaration::imr::{Annotation, DbType, Field, ForeignKey, InternalModelFormat, Model};
+use rorm_declaration::migration::Migration;
+use rorm_sql::insert::Insert;
+use rorm_sql::value::Value;
+use rorm_sql::DBImpl;
+
+use crate::make_migrations::create_models;
+use crate::migrate::config::deserialize_db_conf;
+use crate::migrate::{
+    connect_and_create_last_migration_table, get_last_migration, history_row,
+    last_migration_table_name,
+};
+use crate::utils::introspection::{introspect_database, DbColumn, DbTable};
+use crate::utils::migrations::{
+    convert_migration_to_file, get_migration_files, migration_checksum, record_checksum,
+};
+use crate::utils::output::{print_sql, report, Event};
+use crate::{linter, print_warning};
+
+/// Maps the data type of a column to the [DbType] that is created by rorm
+///
+/// Returns [None] for data types that can not be represented.
+fn db_type_of_column(column: &DbColumn, dialect: DBImpl) -> Option<DbType> {
+    let sqlite = match dialect {
+        #[cfg(feature = "sqlite")]
+        DBImpl::SQLite => true,
+        #[allow(unreachable_patterns)]
+        _ => false,
+    };
+    let data_type = column.data_type.to_lowercase();
+    let data_type = data_type
+        .split_once('(')
+        .map_or(data_type.as_str(), |(x, _)| x)
+        .trim();
+
+    Some(match data_type {
+        // SQLite stores all integers with the same affinity
+        "integer" if sqlite => DbType::Int64,
+        "tinyint" => DbType::Int8,
+        "smallint" | "int2" => DbType::Int16,
+        "integer" | "int" | "int4" | "mediumint" | "serial" => DbType::Int32,
+        "bigint" | "int8" | "bigserial" => DbType::Int64,
+        "real" | "float" | "float4" => DbType::Float,
+        "double" | "double precision" | "float8" => DbType::Double,
+        "boolean" | "bool" => DbType::Boolean,
+        "varchar" | "character varying" | "char" | "character" | "text" | "tinytext"
+        | "mediumtext" | "longtext" | "clob" => DbType::VarChar,
+        "blob" | "tinyblob" | "mediumblob" | "longblob" | "binary" | "varbinary" | "bytea" => {
+            DbType::Binary
+        }
+        "date" => DbType::Date,
+        "datetime"
+        | "timestamp"
+        | "timestamptz"
+        | "timestamp without time zone"
+        | "timestamp with time zone" => DbType::DateTime,
+        "time" | "time without time zone" => DbType::Time,
+        _ => return None,
+    })
+}
+
+/// Converts a table found in the database to a model
+///
+/// Columns that can not be represented are skipped with a warning.
+fn table_to_model(table: &DbTable, tables: &[DbTable], dialect: DBImpl) -> Model {
+    let mut fields = vec![];
+
+    for column in &table.columns {
+        let Some(db_type) = db_type_of_column(column, dialect) else {
+            print_warning!(
+                "Skipped column {}.{} as its type {} can not be represented",
+                table.name,
+                column.name,
+                column.data_type
+            );
+            continue;
+        };
+
+        let mut annotations = vec![];
+        if column.primary_key {
+            annotations.push(Annotation::PrimaryKey);
+        } else if !column.nullable {
+            annotations.push(Annotation::NotNull);
+        }
+        if column.auto_increment {
+            annotations.push(Annotation::AutoIncrement);
+        }
+        if column.unique && !column.primary_key {
+            annotations.push(Annotation::Unique);
+        }
+        if db_type == DbType::VarChar {
+            match column.max_length {
+                Some(max_length) => annotations.push(Annotation::MaxLength(max_length as i32)),
+                None => print_warning!(
+                    "Column {}.{} has no maximum length, \
+                    add a MaxLength annotation before applying the migration to another database",
+                    table.name,
+                    column.name
+                ),
+            }
+        }
+        if let Some((table_name, column_name)) = &column.foreign_key {
+            // SQLite omits the referenced column if it is the primary key
+            let column_name = if column_name.is_empty() {
+                tables
+                    .iter()
+                    .find(|x| x.name == *table_name)
+                    .and_then(|x| x.columns.iter().find(|y| y.primary_key))
+                    .map_or(String::new(), |x| x.name.clone())
+            } else {
+                column_name.clone()
+            };
+
+            annotations.push(Annotation::ForeignKey(ForeignKey {
+                table_name: table_name.clone(),
+                column_name,
+                ..Default::default()
+            }));
+        }
+
+        fields.push(Field {
+            name: column.name.clone(),
+            db_type,
+            annotations,
+            source_defined_at: None,
+        });
+    }
+
+    Model {
+        name: table.name.clone(),
+        fields,
+        source_defined_at: None,
+    }
+}
+
+/**
+Creates the initial migration from the schema of an existing database.
+
+The migration is recorded as applied, as the tables exist already.
+Refuses to run if there are any migrations or applied migrations.
+*/
+pub async fn inspect_db(
+    migration_dir: String,
+    database_config: String,
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    let p = Path::new(migration_dir.as_str());
+    if p.is_file() {
+        return Err(anyhow!("Migration directory {migration_dir} is a file"));
+    }
+    create_dir_all(p).with_context(|| "Couldn't create migration directory")?;
+
+    if !get_migration_files(migration_dir.as_str())?.is_empty() {
+        return Err(anyhow!(
+            "Migrations exist already in {migration_dir}, inspect-db can only create the initial migration."
+        ));
+    }
+
+    let db_conf = deserialize_db_conf(Path::new(database_config.as_str()))?;
+
+    let pool = connect_and_create_last_migration_table(&db_conf, log_sql).await?;
+    let last_migration_table_name = last_migration_table_name(&db_conf);
+    let db_impl = (&pool).dialect();
+
+    if let Some(id) = get_last_migration(&pool, last_migration_table_name, log_sql).await? {
+        return Err(anyhow!(
+            "Migration {id:04} has been applied to the database already, \
+            inspect-db can only be used for databases not managed by rorm."
+        ));
+    }
+
+    let tables: Vec<DbTable> = introspect_database(&pool, log_sql)
+        .await?
+        .into_iter()
+        .filter(|x| x.name != last_migration_table_name)
+        .collect();
+
+    if tables.is_empty() {
+        let message = "No tables found.";
+        report(Event::NothingToDo { message }, message);
+        return Ok(());
+    }
+
+    let internal_models = InternalModelFormat {
+        models: tables
+            .iter()
+            .map(|x| table_to_model(x, &tables, db_impl))
+            .collect(),
+    };
+
+    if let Err(e) = linter::check_internal_models(&internal_models) {
+        print_warning!("The inspected models do not pass the model checks: {e}");
+    }
+
+    let operations = create_models(&internal_models.models.iter().collect::<Vec<&Model>>());
+
+    let mut hasher = DefaultHasher::new();
+    internal_models.hash(&mut hasher);
+
+    let migration = Migration {
+        hash: hasher.finish().to_string(),
+        initial: true,
+        id: 1,
+        name: "initial".to_string(),
+        dependency: None,
+        replaces: vec![],
+        operations,
+    };
+
+    let path = p.join(format!("{:04}_{}.toml", migration.id, &migration.name));
+
+    let checksum = migration_checksum(&migration)?;
+    let (columns, row) = history_row(&migration, &checksum, None);
+    let v: &[&[Value]] = &[&row];
+    let (query_string, bind_params) = db_impl
+        .insert(last_migration_table_name, &columns, v, None)
+        .build();
+
+    if log_sql {
+        print_sql(&query_string);
+    }
+
+    // The file is only kept if the migration is recorded as applied,
+    // otherwise migrate would try to create the existing tables
+    let mut tx = pool
+        .start_transaction()
+        .await
+        .with_context(|| "Error while starting transaction")?;
+
+    if let Err(e) = tx.execute::<Nothing>(query_string, bind_params).await {
+        tx.rollback()
+            .await
+            .with_context(|| "Error while rollback in transaction")?;
+        return Err(e).with_context(|| "Error while recording the initial migration as applied");
+    }
+
+    let written = record_checksum(&migration_dir, &migration).and_then(|_| {
+        convert_migration_to_file(migration.clone(), &path)
+            .with_context(|| "Error occurred while converting migration to file")
+    });
+    if let Err(e) = written {
+        tx.rollback()
+            .await
+            .with_context(|| "Error while rollback in transaction")?;
+        return Err(e);
+    }
+
+    if let Err(e) = tx.commit().await {
+        remove_file(&path)
+            .with_context(|| format!("Couldn't remove the unrecorded migration {path:?}"))?;
+        return Err(e).with_context(|| "Error while recording the initial migration as applied");
+    }
+
+    report(
+        Event::FileWritten {
+            path: &path.display().to_string(),
+        },
+        format!(
+            "Written initial migration to {} and recorded it as applied",
+            path.display()
+        ),
+    );
+
+    Ok(())
+}
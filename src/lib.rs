@@ -11,6 +11,11 @@ pub mod make_migrations;
 /// This module is used for applying migrations
 pub mod migrate;
 
+mod check_db;
+mod inspect_db;
 mod linter;
+mod merge_migrations;
+mod show_migrations;
+mod sql_migrate;
 mod squash_migrations;
 mod utils;
@@ -1,13 +1,17 @@
 use clap::Parser;
 
-use crate::entry::{entry, Cli};
+use crate::entry::{entry, report_result, Cli};
 
+pub mod check_db;
 pub mod entry;
 pub mod init;
+pub mod inspect_db;
 pub mod linter;
 pub mod make_migrations;
 pub mod merge_migrations;
 pub mod migrate;
+pub mod show_migrations;
+pub mod sql_migrate;
 pub mod squash_migrations;
 pub mod utils;
 
@@ -15,5 +19,8 @@ pub mod utils;
 async fn main() -> anyhow::Result<()> {
     let cli: Cli = Cli::parse();
 
-    entry(cli).await
+    let result = entry(cli).await;
+    report_result(&result);
+
+    result
 }
@@ -3,17 +3,161 @@ use std::collections::HashMap;
 use std::fs::{create_dir_all, read_to_string};
 use std::hash::{Hash, Hasher};
 use std::path::Path;
+use std::str::FromStr;
 
 use anyhow::{anyhow, Context};
+use clap::ValueEnum;
 use rorm_declaration::imr::{Annotation, Field, InternalModelFormat, Model};
 use rorm_declaration::migration::{Migration, Operation};
+use serde::Deserialize;
 
-use crate::linter;
 use crate::utils::migrations::{
-    convert_migration_to_file, convert_migrations_to_internal_models, get_existing_migrations,
+    convert_migration_to_file, convert_migrations_to_internal_models, get_all_existing_migrations,
+    get_existing_migrations, record_checksum, verify_recorded_checksums,
 };
+use crate::utils::output::{report, Event, UnexpectedChanges};
 use crate::utils::question;
 use crate::utils::re::RE;
+use crate::{linter, print_message, print_warning};
+
+/// Name of the file in the migration directory that contains hints for renamed models and fields
+pub const RENAME_HINTS_FILE: &str = "renames.toml";
+
+/// Decides whether detected renames are assumed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RenamePolicy {
+    /// Ask for every detected rename, models and fields given as hint are renamed without asking
+    Ask,
+    /// Never assume a rename, renamed models and fields are deleted and created again
+    Never,
+    /// Assume a rename for every detected rename
+    Always,
+    /// Only assume renames that are given as hint
+    Hints,
+}
+
+/// A rename given with --rename
+///
+/// `old=new` renames a model, `model.old=new` renames a field of the model `model`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RenameHint {
+    /// Model `old` was renamed to `new`
+    Model { old: String, new: String },
+    /// Field `old` of the model `model` was renamed to `new`
+    Field {
+        model: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl FromStr for RenameHint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((old, new)) = s.split_once('=') else {
+            return Err("Expected old=new or model.old=new".to_string());
+        };
+
+        let hint = match old.split_once('.') {
+            None => RenameHint::Model {
+                old: old.to_string(),
+                new: new.to_string(),
+            },
+            Some((model, old)) => RenameHint::Field {
+                model: model.to_string(),
+                old: old.to_string(),
+                new: new.to_string(),
+            },
+        };
+
+        let names = match &hint {
+            RenameHint::Model { old, new } => vec![old, new],
+            RenameHint::Field { model, old, new } => vec![model, old, new],
+        };
+        if names.iter().any(|x| x.is_empty() || x.contains('.')) {
+            return Err(format!("Invalid rename {s}"));
+        }
+
+        Ok(hint)
+    }
+}
+
+/**
+Hints for renamed models and fields, see [RENAME_HINTS_FILE]
+
+In contrast to detected renames, hinted models and fields may have been changed as well.
+The changes are applied after the rename.
+
+The new names are mapped to the previous names:
+
+```toml
+[models]
+new_model = "old_model"
+
+[fields.model]
+new_field = "old_field"
+```
+*/
+#[derive(Debug, Default, Deserialize)]
+pub struct RenameHints {
+    /// Mapping: New model name -> Old model name
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+    /// Mapping: Model name -> (New field name -> Old field name)
+    #[serde(default)]
+    pub fields: HashMap<String, HashMap<String, String>>,
+}
+
+impl RenameHints {
+    /**
+    Reads the hints file of the migration directory, if it exists, and adds the given hints.
+
+    `migration_dir`: [&str]: The directory the hints file is located in.
+    `renames`: [&[RenameHint]]: Additional hints.
+     */
+    pub fn load(migration_dir: &str, renames: &[RenameHint]) -> anyhow::Result<Self> {
+        let path = Path::new(migration_dir).join(RENAME_HINTS_FILE);
+        let mut hints: RenameHints = if path.exists() {
+            let toml_str = read_to_string(&path)
+                .with_context(|| format!("Error occurred while reading {}", path.display()))?;
+            toml::from_str(toml_str.as_str())
+                .with_context(|| format!("Error while deserializing {}", path.display()))?
+        } else {
+            RenameHints::default()
+        };
+
+        for rename in renames {
+            match rename {
+                RenameHint::Model { old, new } => {
+                    hints.models.insert(new.clone(), old.clone());
+                }
+                RenameHint::Field { model, old, new } => {
+                    hints
+                        .fields
+                        .entry(model.clone())
+                        .or_default()
+                        .insert(new.clone(), old.clone());
+                }
+            }
+        }
+
+        Ok(hints)
+    }
+
+    /// Checks whether the model `new` was previously named `old`
+    pub fn is_model_renamed(&self, old: &str, new: &str) -> bool {
+        self.models.get(new).is_some_and(|x| x == old)
+    }
+
+    /// Checks whether the field `new` of the model `model` was previously named `old`
+    pub fn is_field_renamed(&self, model: &str, old: &str, new: &str) -> bool {
+        self.fields
+            .get(model)
+            .and_then(|x| x.get(new))
+            .is_some_and(|x| x == old)
+    }
+}
 
 /// Options struct for [run_make_migrations]
 #[derive(Debug)]
@@ -28,6 +172,181 @@ pub struct MakeMigrationsOptions {
     pub non_interactive: bool,
     /// If set, all warnings are suppressed
     pub warnings_disabled: bool,
+    /// Ids of migrations whose changes are accepted
+    pub accept_changed: Vec<u16>,
+    /// If set, only check for changes without writing a migration or asking questions
+    pub check: bool,
+    /// Policy for detected renames, defaults to [RenamePolicy::Ask] if interactive
+    /// and to [RenamePolicy::Hints] otherwise
+    pub rename_policy: Option<RenamePolicy>,
+    /// Renames in addition to the hints file
+    pub renames: Vec<RenameHint>,
+}
+
+impl Default for MakeMigrationsOptions {
+    fn default() -> Self {
+        Self {
+            models_file: String::from("./.models.json"),
+            migration_dir: String::from("./migrations/"),
+            name: None,
+            non_interactive: false,
+            warnings_disabled: false,
+            accept_changed: vec![],
+            check: false,
+            rename_policy: None,
+            renames: vec![],
+        }
+    }
+}
+
+impl MakeMigrationsOptions {
+    /// Retrieves the policy for detected renames
+    pub fn effective_rename_policy(&self) -> RenamePolicy {
+        match self.rename_policy {
+            // Questions can't be asked without interaction
+            Some(RenamePolicy::Ask) | None if self.non_interactive || self.check => {
+                RenamePolicy::Hints
+            }
+            Some(policy) => policy,
+            None => RenamePolicy::Ask,
+        }
+    }
+}
+
+/**
+Helper function to decide whether a detected rename is assumed.
+
+`policy`: [RenamePolicy]: The policy for detected renames.
+`hinted`: [bool]: Whether the rename is given as hint.
+`question_text`: [&str]: Question to ask if the policy is [RenamePolicy::Ask].
+ */
+fn assume_rename(policy: RenamePolicy, hinted: bool, question_text: &str) -> bool {
+    match policy {
+        RenamePolicy::Ask => hinted || question(question_text),
+        RenamePolicy::Never => false,
+        RenamePolicy::Always => true,
+        RenamePolicy::Hints => hinted,
+    }
+}
+
+/**
+Helper function to check whether existing rows need a value for a new field.
+
+Adding such a field fails if the table already contains rows.
+
+`field`: [&Field]: The field that is added to an existing model.
+ */
+fn requires_backfill(field: &Field) -> bool {
+    field.annotations.contains(&Annotation::NotNull)
+        && !field.annotations.iter().any(|x| {
+            matches!(
+                x,
+                Annotation::DefaultValue(_)
+                    | Annotation::AutoCreateTime
+                    | Annotation::AutoUpdateTime
+                    | Annotation::AutoIncrement
+            )
+        })
+}
+
+/**
+Helper function to retrieve the new model a field references, if it is not created yet.
+
+`field`: [&Field]: The field to check.
+`model`: [&str]: Name of the model of the field, references to itself are ignored.
+`pending`: [&[&Model]]: The new models that are not created yet.
+ */
+fn pending_reference<'a>(field: &'a Field, model: &str, pending: &[&Model]) -> Option<&'a str> {
+    field.annotations.iter().find_map(|x| match x {
+        Annotation::ForeignKey(fk)
+            if fk.table_name != model && pending.iter().any(|y| y.name == fk.table_name) =>
+        {
+            Some(fk.table_name.as_str())
+        }
+        _ => None,
+    })
+}
+
+/**
+Helper function to create the operations for new models.
+
+Models are created after the new models they reference, so their foreign keys are
+created with them. If models reference each other in a cycle, the cycle is reported
+and the foreign keys closing it are created afterwards.
+
+`models`: [&[&Model]]: The new models.
+ */
+pub(crate) fn create_models(models: &[&Model]) -> Vec<Operation> {
+    let mut pending = models.to_vec();
+    let mut operations = vec![];
+    let mut deferred = vec![];
+
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|x| {
+            x.fields
+                .iter()
+                .all(|y| pending_reference(y, &x.name, &pending).is_none())
+        });
+
+        let idx = match ready {
+            Some(idx) => idx,
+            None => {
+                // Every pending model references another pending model,
+                // so following the references leads into a cycle
+                let mut visited = vec![];
+                let mut current = 0;
+                while !visited.contains(&current) {
+                    visited.push(current);
+                    let model = pending[current];
+                    current = model
+                        .fields
+                        .iter()
+                        .find_map(|x| pending_reference(x, &model.name, &pending))
+                        .and_then(|x| pending.iter().position(|y| y.name == x))
+                        .expect("Pending models without references should be ready");
+                }
+                current
+            }
+        };
+
+        let model = pending.remove(idx);
+        let (fields, cyclic): (Vec<Field>, Vec<Field>) = model
+            .fields
+            .iter()
+            .cloned()
+            .partition(|x| pending_reference(x, &model.name, &pending).is_none());
+
+        if !cyclic.is_empty() {
+            print_message!(
+                "Model {} is part of a cycle of foreign keys, its fields {} are created after the models.",
+                model.name,
+                cyclic
+                    .iter()
+                    .map(|x| x.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        operations.push(Operation::CreateModel {
+            name: model.name.clone(),
+            fields,
+        });
+        report(
+            Event::ModelCreated {
+                model: model.name.as_str(),
+            },
+            format!("Created model {}", model.name),
+        );
+
+        deferred.extend(cyclic.into_iter().map(|field| Operation::CreateField {
+            model: model.name.clone(),
+            field,
+        }));
+    }
+
+    operations.extend(deferred);
+    operations
 }
 
 /**
@@ -86,6 +405,26 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
     let existing_migrations = get_existing_migrations(&options.migration_dir)
         .with_context(|| "An error occurred while deserializing migrations")?;
 
+    verify_recorded_checksums(
+        &options.migration_dir,
+        &get_all_existing_migrations(&options.migration_dir)?,
+        &options.accept_changed,
+        options.check,
+    )
+    .with_context(|| "Checksum verification of existing migrations failed")?;
+
+    let rename_policy = options.effective_rename_policy();
+    let rename_hints = RenameHints::load(&options.migration_dir, &options.renames)?;
+    let policy_name = rename_policy
+        .to_possible_value()
+        .map_or(String::new(), |x| x.get_name().to_string());
+    report(
+        Event::RenamePolicy {
+            policy: &policy_name,
+        },
+        format!("Using rename policy {policy_name}"),
+    );
+
     let mut hasher = DefaultHasher::new();
     internal_models.hash(&mut hasher);
     let h = hasher.finish();
@@ -97,7 +436,8 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
 
         // If hash matches with the one of the current models, exiting
         if last_migration.hash == h.to_string() {
-            println!("No changes - nothing to do.");
+            let message = "No changes - nothing to do.";
+            report(Event::NothingToDo { message }, message);
             return Ok(());
         }
 
@@ -109,7 +449,7 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
 
         let mut op: Vec<Operation> = vec![];
 
-        let old_lookup: HashMap<String, &Model> = constructed
+        let mut old_lookup: HashMap<String, &Model> = constructed
             .models
             .iter()
             .map(|x| (x.name.clone(), x))
@@ -131,7 +471,24 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
         let mut new_fields: HashMap<String, Vec<&Field>> = HashMap::new();
         let mut deleted_fields: HashMap<String, Vec<&Field>> = HashMap::new();
         // Mapping: Model name -> (Old field, new field)
-        let mut altered_fields: HashMap<String, Vec<(&Field, &Field)>> = HashMap::new();
+        let mut altered_fields: HashMap<String, Vec<(Field, Field)>> = HashMap::new();
+
+        // Models renamed according to the hints are compared with their previous state
+        // under their new name, so changes of their fields follow the rename
+        if rename_policy != RenamePolicy::Never {
+            for x in &internal_models.models {
+                let Some(old_name) = rename_hints.models.get(&x.name) else {
+                    continue;
+                };
+                if old_lookup.contains_key(&x.name) || new_lookup.contains_key(old_name) {
+                    continue;
+                }
+                if let Some(y) = old_lookup.remove(old_name) {
+                    old_lookup.insert(x.name.clone(), y);
+                    renamed_models.push((y, x));
+                }
+            }
+        }
 
         // Check if any new models exist
         internal_models.models.iter().for_each(|x| {
@@ -142,7 +499,9 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
 
         // Check if any old model got deleted
         constructed.models.iter().for_each(|x| {
-            if !new_lookup.iter().any(|(a, _)| x.name == *a) {
+            if !new_lookup.iter().any(|(a, _)| x.name == *a)
+                && !renamed_models.iter().any(|(a, _)| a.name == x.name)
+            {
                 deleted_models.push(x);
             }
         });
@@ -186,7 +545,10 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
                             if altered_fields.get(x.name.as_str()).is_none() {
                                 altered_fields.insert(x.name.clone(), vec![]);
                             }
-                            altered_fields.get_mut(&x.name).unwrap().push((y, z));
+                            altered_fields
+                                .get_mut(&x.name)
+                                .unwrap()
+                                .push((y.clone(), z.clone()));
                         }
                     });
                 });
@@ -196,13 +558,26 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
         if !new_models.is_empty() && !deleted_models.is_empty() {
             for x in &new_models {
                 for y in &deleted_models {
+                    // A model can only be renamed once
+                    if renamed_models.iter().any(|(a, b)| a == y || b == x) {
+                        continue;
+                    }
+
                     if x.fields == y.fields
-                        && question(
+                        && assume_rename(
+                            rename_policy,
+                            false,
                             format!("Did you rename the model {} to {}?", &y.name, &x.name)
                                 .as_str(),
                         )
                     {
-                        println!("Renamed model {} to {}.", &y.name, &x.name);
+                        if rename_policy == RenamePolicy::Always && !options.warnings_disabled {
+                            print_warning!(
+                                "Assumed that model {} was renamed to {}",
+                                &y.name,
+                                &x.name
+                            );
+                        }
                         renamed_models.push((y, x));
                     }
                 }
@@ -217,76 +592,97 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
             op.push(Operation::RenameModel {
                 old: old.name.clone(),
                 new: new.name.clone(),
-            })
+            });
+            report(
+                Event::ModelRenamed {
+                    old: old.name.as_str(),
+                    new: new.name.as_str(),
+                },
+                format!("Renamed model {} to {}.", &old.name, &new.name),
+            );
         }
 
-        let mut references: HashMap<String, Vec<Field>> = HashMap::new();
-
         // Create migration operations for new models
-        new_models.iter().for_each(|x| {
-            let mut normal_fields = vec![];
-
-            for y in &x.fields {
-                if y.annotations
-                    .iter()
-                    .any(|z| z.eq_shallow(&Annotation::ForeignKey(Default::default())))
-                {
-                    references
-                        .entry(x.name.clone())
-                        .or_default()
-                        .push(y.clone());
-                } else {
-                    normal_fields.push(y.clone());
-                }
-            }
-
-            op.push(Operation::CreateModel {
-                name: x.name.clone(),
-                fields: normal_fields,
-            });
-            println!("Created model {}", x.name);
-        });
-
-        // Create referencing fields for new models
-        for (model, fields) in references {
-            for field in fields {
-                op.push(Operation::CreateField {
-                    model: model.clone(),
-                    field,
-                });
-            }
-        }
+        op.extend(create_models(&new_models));
 
         // Create migration operations for deleted models
         deleted_models.iter().for_each(|x| {
             op.push(Operation::DeleteModel {
                 name: x.name.clone(),
             });
-            println!("Deleted model {}", x.name);
+            report(
+                Event::ModelDeleted {
+                    model: x.name.as_str(),
+                },
+                format!("Deleted model {}", x.name),
+            );
         });
 
         for (x, new_fields) in &new_fields {
             if let Some(old_fields) = deleted_fields.get(x) {
                 for new_field in new_fields {
                     for old_field in old_fields {
-                        if new_field.db_type == old_field.db_type
-                            && new_field.annotations == old_field.annotations
-                            && question(
-                                format!(
-                                    "Did you rename the field {} of model {} to {}?",
-                                    &old_field.name, &x, &new_field.name
-                                )
-                                .as_str(),
-                            )
+                        // A field can only be renamed once
+                        if renamed_fields.get(x).is_some_and(|f| {
+                            f.iter()
+                                .any(|(a, b)| a.name == old_field.name || b.name == new_field.name)
+                        }) {
+                            continue;
+                        }
+
+                        let hinted =
+                            rename_hints.is_field_renamed(x, &old_field.name, &new_field.name);
+                        let unchanged = new_field.db_type == old_field.db_type
+                            && new_field.annotations == old_field.annotations;
+
+                        // Fields renamed according to the hints may be changed as well
+                        if (hinted && rename_policy != RenamePolicy::Never)
+                            || (unchanged
+                                && assume_rename(
+                                    rename_policy,
+                                    hinted,
+                                    format!(
+                                        "Did you rename the field {} of model {} to {}?",
+                                        &old_field.name, &x, &new_field.name
+                                    )
+                                    .as_str(),
+                                ))
                         {
+                            if !unchanged {
+                                altered_fields.entry(x.clone()).or_default().push((
+                                    Field {
+                                        name: new_field.name.clone(),
+                                        ..(*old_field).clone()
+                                    },
+                                    (*new_field).clone(),
+                                ));
+                            }
+                            if rename_policy == RenamePolicy::Always
+                                && !hinted
+                                && !options.warnings_disabled
+                            {
+                                print_warning!(
+                                    "Assumed that field {} of model {} was renamed to {}",
+                                    &old_field.name,
+                                    &x,
+                                    &new_field.name
+                                );
+                            }
                             if !renamed_fields.contains_key(x) {
                                 renamed_fields.insert(x.clone(), vec![]);
                             }
                             let f = renamed_fields.get_mut(x).unwrap();
                             f.push((old_field, new_field));
-                            println!(
-                                "Renamed field {} of model {} to {}.",
-                                &new_field.name, &x, &old_field.name
+                            report(
+                                Event::FieldRenamed {
+                                    model: x.as_str(),
+                                    old: old_field.name.as_str(),
+                                    new: new_field.name.as_str(),
+                                },
+                                format!(
+                                    "Renamed field {} of model {} to {}.",
+                                    &old_field.name, &x, &new_field.name
+                                ),
                             );
                         }
                     }
@@ -317,11 +713,26 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
         // Create migration operations for new fields in existing models
         new_fields.iter().for_each(|(x, y)| {
             y.iter().for_each(|z| {
+                if requires_backfill(z) && !options.warnings_disabled {
+                    print_warning!(
+                        "Field {} is added to model {} as not null without a default value. \
+                        Applying the migration fails if the table contains rows. \
+                        Add a default value or fill the column in a RawSQL operation instead.",
+                        &z.name,
+                        &x
+                    );
+                }
                 op.push(Operation::CreateField {
                     model: x.clone(),
                     field: (*z).clone(),
                 });
-                println!("Added field {} to model {}", z.name, x);
+                report(
+                    Event::FieldCreated {
+                        model: x.as_str(),
+                        field: z.name.as_str(),
+                    },
+                    format!("Added field {} to model {}", z.name, x),
+                );
             })
         });
 
@@ -332,19 +743,45 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
                     model: x.clone(),
                     name: z.name.clone(),
                 });
-                println!("Deleted field {} from model {}", z.name, x);
+                report(
+                    Event::FieldDeleted {
+                        model: x.as_str(),
+                        field: z.name.as_str(),
+                    },
+                    format!("Deleted field {} from model {}", z.name, x),
+                );
             })
         });
 
         // Create migration operations for altered fields in existing models
         altered_fields.iter().for_each(|(model, af)| {
             af.iter().for_each(|(old, new)| {
+                // Altering columns in place is not supported by the migration format yet
+                if !options.warnings_disabled {
+                    print_warning!(
+                        "Field {} of model {} is deleted and created again, its data is lost. \
+                        To keep the data, alter the column in a RawSQL operation instead.",
+                        &new.name,
+                        &model
+                    );
+                }
+                // Fields renamed according to the hints with changes are recreated as well
+                if requires_backfill(new) && !options.warnings_disabled {
+                    print_warning!(
+                        "Field {} is created again on model {} as not null without a default value. \
+                        Applying the migration fails if the table contains rows. \
+                        Add a default value or fill the column in a RawSQL operation instead.",
+                        &new.name,
+                        &model
+                    );
+                }
+
                 // Check datatype
                 if old.db_type != new.db_type {
                     match (old.db_type, new.db_type) {
-                        // TODO:
-                        // There are cases where columns can be altered
-                        // e.g. i8 -> i16 or float -> double
+                        // Widening changes like i8 -> i16 or float -> double could keep the data,
+                        // but the migration format has no operation to alter a column in place.
+                        // This is blocked until rorm-declaration adds such an Operation variant.
 
                         // Default case
                         (_, _) => {
@@ -356,7 +793,13 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
                                 model: model.clone(),
                                 field: (*new).clone(),
                             });
-                            println!("Recreated field {} on model {}", &new.name, &model);
+                            report(
+                                Event::FieldRecreated {
+                                    model: model.as_str(),
+                                    field: new.name.as_str(),
+                                },
+                                format!("Recreated field {} on model {}", &new.name, &model),
+                            );
                         }
                     }
                 } else {
@@ -369,7 +812,13 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
                         model: model.clone(),
                         field: (*new).clone(),
                     });
-                    println!("Recreated field {} on model {}", &new.name, &model);
+                    report(
+                        Event::FieldRecreated {
+                            model: model.as_str(),
+                            field: new.name.as_str(),
+                        },
+                        format!("Recreated field {} on model {}", &new.name, &model),
+                    );
                 }
             });
         });
@@ -386,46 +835,11 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
     } else {
         // If there are no models yet, no migrations must be created
         if internal_models.models.is_empty() {
-            println!("No models found.");
+            let message = "No models found.";
+            report(Event::NothingToDo { message }, message);
         // New migration must be generated as no migration exists
         } else {
-            let mut operations = vec![];
-            let mut references: HashMap<String, Vec<Field>> = HashMap::new();
-
-            operations.extend(internal_models.models.iter().map(|x| {
-                let mut normal_fields = vec![];
-
-                for y in &x.fields {
-                    if y.annotations
-                        .iter()
-                        .any(|z| z.eq_shallow(&Annotation::ForeignKey(Default::default())))
-                    {
-                        references
-                            .entry(x.name.clone())
-                            .or_default()
-                            .push(y.clone());
-                    } else {
-                        normal_fields.push(y.clone());
-                    }
-                }
-
-                let o = Operation::CreateModel {
-                    name: x.name.clone(),
-                    fields: normal_fields,
-                };
-                println!("Created model {}", x.name);
-                o
-            }));
-
-            operations.extend(references.into_iter().flat_map(|(model, fields)| {
-                fields
-                    .iter()
-                    .map(|field| Operation::CreateField {
-                        model: model.clone(),
-                        field: field.clone(),
-                    })
-                    .collect::<Vec<Operation>>()
-            }));
+            let operations = create_models(&internal_models.models.iter().collect::<Vec<&Model>>());
 
             new_migration = Some(Migration {
                 hash: h.to_string(),
@@ -442,15 +856,131 @@ pub fn run_make_migrations(options: MakeMigrationsOptions) -> anyhow::Result<()>
         }
     }
 
+    if options.check {
+        return match new_migration {
+            Some(migration) if !migration.operations.is_empty() => Err(UnexpectedChanges(format!(
+                "The models do not match the migrations, {} operations are missing. \
+                    Run make-migrations to create the migration.",
+                migration.operations.len()
+            ))
+            .into()),
+            _ => {
+                let message = "The models match the migrations.";
+                report(Event::NothingToDo { message }, message);
+                Ok(())
+            }
+        };
+    }
+
     if let Some(migration) = new_migration {
         // Write migration to disk
         let path = Path::new(options.migration_dir.as_str())
             .join(format!("{:04}_{}.toml", migration.id, &migration.name));
+        record_checksum(&options.migration_dir, &migration)?;
         convert_migration_to_file(migration, &path)
             .with_context(|| "Error occurred while converting migration to file")?;
+        report(
+            Event::FileWritten {
+                path: &path.display().to_string(),
+            },
+            format!("Written migration to {}", path.display()),
+        );
     }
 
-    println!("Done.");
+    print_message!("Done.");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use rorm_declaration::imr::{Annotation, DbType, DefaultValue, Field, ForeignKey, Model};
+    use rorm_declaration::migration::Operation;
+
+    use crate::make_migrations::{create_models, requires_backfill, RenameHint};
+
+    #[test]
+    fn test_parse_rename_hint() {
+        assert_eq!(
+            "foo=bar".parse(),
+            Ok(RenameHint::Model {
+                old: "foo".to_string(),
+                new: "bar".to_string(),
+            })
+        );
+        assert_eq!(
+            "foo.bar=baz".parse(),
+            Ok(RenameHint::Field {
+                model: "foo".to_string(),
+                old: "bar".to_string(),
+                new: "baz".to_string(),
+            })
+        );
+        assert!("foo".parse::<RenameHint>().is_err());
+        assert!("foo.bar=baz.qux".parse::<RenameHint>().is_err());
+        assert!("=bar".parse::<RenameHint>().is_err());
+    }
+
+    #[test]
+    fn test_requires_backfill() {
+        let field = |annotations| Field {
+            name: "foo".to_string(),
+            db_type: DbType::Int32,
+            annotations,
+            source_defined_at: None,
+        };
+
+        assert!(requires_backfill(&field(vec![Annotation::NotNull])));
+        assert!(!requires_backfill(&field(vec![])));
+        assert!(!requires_backfill(&field(vec![
+            Annotation::NotNull,
+            Annotation::DefaultValue(DefaultValue::Integer(0)),
+        ])));
+    }
+
+    #[test]
+    fn test_create_models_in_dependency_order() {
+        let model = |name: &str, references: &[&str]| Model {
+            name: name.to_string(),
+            fields: references
+                .iter()
+                .map(|x| Field {
+                    name: format!("{x}_id"),
+                    db_type: DbType::Int64,
+                    annotations: vec![Annotation::ForeignKey(ForeignKey {
+                        table_name: x.to_string(),
+                        column_name: "id".to_string(),
+                        ..Default::default()
+                    })],
+                    source_defined_at: None,
+                })
+                .collect(),
+            source_defined_at: None,
+        };
+        let created = |operations: &[Operation]| {
+            operations
+                .iter()
+                .map(|x| match x {
+                    Operation::CreateModel { name, fields } => format!("{name}:{}", fields.len()),
+                    Operation::CreateField { model, field } => format!("{model}.{}", field.name),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let post = model("post", &["user", "post"]);
+        let user = model("user", &[]);
+        assert_eq!(
+            created(&create_models(&[&post, &user])),
+            vec!["user:0", "post:2"]
+        );
+
+        let a = model("a", &["b"]);
+        let b = model("b", &["a"]);
+        let c = model("c", &["a"]);
+        assert_eq!(
+            created(&create_models(&[&c, &a, &b])),
+            vec!["a:0", "c:1", "b:1", "a.b_id"]
+        );
+    }
+}
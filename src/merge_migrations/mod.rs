@@ -0,0 +1,633 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs::remove_file;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use rorm_declaration::imr::Annotation;
+use rorm_declaration::migration::{Migration, Operation};
+
+use crate::migrate::config::{database_url, deserialize_db_conf};
+use crate::migrate::{
+    connect_database, get_applied_migrations_read_only, last_migration_table_name,
+};
+use crate::utils::migrations::{
+    convert_file_to_migration, convert_migration_to_file, convert_migrations_to_internal_models,
+    get_migration_files, is_atomic, mark_non_atomic, record_checksum,
+};
+use crate::utils::output::{output_format, report, Event, OutputFormat};
+use crate::utils::question;
+use crate::{print_message, print_warning};
+
+/// Models and fields that are changed by a list of operations
+#[derive(Default)]
+struct Touched {
+    /// Models that are created, renamed or deleted
+    models: HashSet<String>,
+    /// Models that have fields changed or are referenced by a foreign key
+    field_models: HashSet<String>,
+    /// Pairs of model and field that are changed
+    fields: HashSet<(String, String)>,
+}
+
+impl Touched {
+    fn from_operations(operations: &[Operation]) -> anyhow::Result<Self> {
+        let mut touched = Self::default();
+
+        for operation in operations {
+            match operation {
+                Operation::CreateModel { name, fields } => {
+                    touched.models.insert(name.clone());
+                    for field in fields {
+                        touched.add_references(&field.annotations);
+                    }
+                }
+                Operation::RenameModel { old, new } => {
+                    touched.models.insert(old.clone());
+                    touched.models.insert(new.clone());
+                }
+                Operation::DeleteModel { name } => {
+                    touched.models.insert(name.clone());
+                }
+                Operation::CreateField { model, field } => {
+                    touched.add_field(model, &field.name);
+                    touched.add_references(&field.annotations);
+                }
+                Operation::RenameField {
+                    table_name,
+                    old,
+                    new,
+                } => {
+                    touched.add_field(table_name, old);
+                    touched.add_field(table_name, new);
+                }
+                Operation::DeleteField { model, name } => {
+                    touched.add_field(model, name);
+                }
+                Operation::RawSQL { structure_safe, .. } => {
+                    if !structure_safe {
+                        return Err(anyhow!(
+                            "RawSQL operation without StructureSafe flag found, \
+                            can not determine if both branches are compatible."
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(touched)
+    }
+
+    fn add_field(&mut self, model: &str, field: &str) {
+        self.field_models.insert(model.to_string());
+        self.fields.insert((model.to_string(), field.to_string()));
+    }
+
+    fn add_references(&mut self, annotations: &[Annotation]) {
+        for annotation in annotations {
+            if let Annotation::ForeignKey(fk) = annotation {
+                self.field_models.insert(fk.table_name.clone());
+            }
+        }
+    }
+
+    /// Returns a description for every change that is made by both lists of operations
+    fn conflicts(&self, other: &Self) -> Vec<String> {
+        let mut conflicts: Vec<String> = self
+            .models
+            .iter()
+            .filter(|x| other.models.contains(*x) || other.field_models.contains(*x))
+            .chain(
+                other
+                    .models
+                    .iter()
+                    .filter(|x| !self.models.contains(*x) && self.field_models.contains(*x)),
+            )
+            .map(|x| format!("model {x}"))
+            .chain(
+                self.fields
+                    .intersection(&other.fields)
+                    .map(|(model, field)| format!("field {field} of model {model}")),
+            )
+            .collect();
+        conflicts.sort();
+        conflicts
+    }
+}
+
+/**
+Assigns the migrations following the fork to the two branches.
+
+Dependencies refer to migration ids and both branches continue with the same ids,
+so the dependencies can't tell the branches apart. Whenever it is ambiguous which
+branch a migration continues, `continues_first` decides.
+
+`migrations`: [&[Migration]]: All migrations that are not squashed.
+`heads`: [[usize; 2]]: Index of the first migration of each branch.
+`continues_first`: [FnMut(&Migration, &Migration, &Migration) -> anyhow::Result<bool>]: Decides
+whether a migration continues the first branch, ending with the second argument,
+instead of the second branch, ending with the third argument.
+*/
+fn split_branches(
+    migrations: &[Migration],
+    heads: [usize; 2],
+    mut continues_first: impl FnMut(&Migration, &Migration, &Migration) -> anyhow::Result<bool>,
+) -> anyhow::Result<[Vec<usize>; 2]> {
+    let mut branches = [vec![heads[0]], vec![heads[1]]];
+    let mut open = [true, true];
+    let mut id = migrations[heads[0]].id;
+
+    while branches[0].len() + branches[1].len() <= migrations.len() {
+        id += 1;
+        let candidates: Vec<usize> = migrations
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.id == id && x.dependency == Some(id - 1))
+            .map(|(idx, _)| idx)
+            .collect();
+        let tails = [
+            &migrations[branches[0][branches[0].len() - 1]],
+            &migrations[branches[1][branches[1].len() - 1]],
+        ];
+
+        match (candidates.as_slice(), open) {
+            ([], _) => return Ok(branches),
+            ([candidate], [true, false]) => branches[0].push(*candidate),
+            ([candidate], [false, true]) => branches[1].push(*candidate),
+            ([candidate], _) => {
+                // Only one branch continues, the other one ends here
+                let branch = usize::from(!continues_first(
+                    &migrations[*candidate],
+                    tails[0],
+                    tails[1],
+                )?);
+                branches[branch].push(*candidate);
+                open[1 - branch] = false;
+            }
+            ([a, b], [true, true]) => {
+                if continues_first(&migrations[*a], tails[0], tails[1])? {
+                    branches[0].push(*a);
+                    branches[1].push(*b);
+                } else {
+                    branches[0].push(*b);
+                    branches[1].push(*a);
+                }
+            }
+            ([_, _], _) => {
+                return Err(anyhow!(
+                    "Found two migrations with id {id:04}, but only one branch continues."
+                ))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Found more than two migrations with id {id:04}, \
+                    only two branches can be merged at once."
+                ))
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "Found a cycle in the dependencies of the migrations."
+    ))
+}
+
+/// Asks the user which branch a migration continues, see [split_branches]
+///
+/// Without interaction the merge fails instead, as guessing could move the deployed branch.
+fn ask_branch(
+    interactive: bool,
+) -> impl FnMut(&Migration, &Migration, &Migration) -> anyhow::Result<bool> {
+    move |migration: &Migration, first: &Migration, second: &Migration| {
+        let text = format!(
+            "Dependencies can't tell the branches apart. Does migration {:04}_{} continue \
+            the branch ending with {:04}_{} (y) instead of the one ending with {:04}_{} (N)?",
+            migration.id, migration.name, first.id, first.name, second.id, second.name
+        );
+
+        if interactive {
+            Ok(question(text.as_str()))
+        } else {
+            Err(anyhow!(
+                "Dependencies can't tell whether migration {:04}_{} continues the branch ending \
+                with {:04}_{} or the one ending with {:04}_{}. Run merge-migrations interactively \
+                to decide.",
+                migration.id,
+                migration.name,
+                first.id,
+                first.name,
+                second.id,
+                second.name
+            ))
+        }
+    }
+}
+
+/**
+Checks that none of the migrations to move has been applied to the configured database.
+
+Databases record the ids of applied migrations, so a moved migration that has been applied
+would be mistaken for the migration of the other branch with the same id.
+If no database is configured, the check is skipped with a warning.
+A configured database that can't be reached is an error, unless `skip_db_check` is set.
+
+`moved`: [&[&Migration]]: The migrations that are moved into the merge migration.
+`database_config`: [&str]: Path to the database configuration file.
+`skip_db_check`: [bool]: Skip the check.
+`log_sql`: [bool]: Log all SQL statements.
+*/
+async fn check_not_applied(
+    moved: &[&Migration],
+    database_config: &str,
+    skip_db_check: bool,
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    if skip_db_check {
+        print_warning!(
+            "Skipped checking whether the migrations to move have been applied. \
+            Make sure none of them has been deployed."
+        );
+        return Ok(());
+    }
+
+    if database_url().is_none() && !Path::new(database_config).exists() {
+        print_warning!(
+            "Couldn't find the database configuration in {database_config}, \
+            can not check whether the migrations to move have been applied."
+        );
+        return Ok(());
+    }
+
+    let db_conf = deserialize_db_conf(Path::new(database_config))?;
+    let pool = connect_database(&db_conf).await.with_context(|| {
+        "Couldn't connect to the database to check whether the migrations to move have been \
+        applied. Skip the check with --skip-db-check if none of them has been deployed."
+    })?;
+    let applied =
+        get_applied_migrations_read_only(&pool, last_migration_table_name(&db_conf), log_sql)
+            .await?;
+
+    // Migrations recorded by older versions have no name, so their branch is unknown
+    if let Some(migration) = moved.iter().find(|x| {
+        applied
+            .iter()
+            .any(|y| y.id == x.id && (y.name.is_none() || y.name.as_ref() == Some(&x.name)))
+    }) {
+        return Err(anyhow!(
+            r#"Migration {:04}_{} of the branch to move may have been applied to the database.
+
+Moving it would make the database mistake the other branch's migration {:04} for applied.
+Keep its branch with --keep {:04}_{} instead."#,
+            migration.id,
+            migration.name,
+            migration.id,
+            migration.id,
+            migration.name
+        ));
+    }
+
+    Ok(())
+}
+
+/**
+Merges two diverged branches of migrations.
+
+The branch given by `keep` is kept, otherwise the longer one. The operations of the
+other branch are moved into a new merge migration that depends on the head of the
+kept branch. The moved migrations are removed, so the merge is refused if any of
+them has been applied to the configured database.
+
+`migration_dir`: [String]: The directory the migrations are located in.
+`keep`: [Option<String>]: Any migration of the branch to keep, as `<ID>_<NAME>`.
+`database_config`: [String]: Path to the database configuration file.
+`log_sql`: [bool]: Log all SQL statements.
+`non_interactive`: [bool]: Fail instead of asking which branch a migration continues.
+`skip_db_check`: [bool]: Don't check whether the migrations to move have been applied.
+*/
+pub async fn merge_migrations(
+    migration_dir: String,
+    keep: Option<String>,
+    database_config: String,
+    log_sql: bool,
+    non_interactive: bool,
+    skip_db_check: bool,
+) -> anyhow::Result<()> {
+    let p = Path::new(migration_dir.as_str());
+    if !p.exists() || p.is_file() {
+        print_message!(
+            "Couldn't find the migration directory in {} \n\n\
+            You can specify an alternative path with --migration-dir <PATH>",
+            migration_dir.as_str()
+        );
+        return Ok(());
+    }
+
+    let mut files = get_migration_files(&migration_dir)?;
+    files.sort_by_key(|x| x.file_name());
+
+    let mut paths = vec![];
+    let mut migrations: Vec<Migration> = vec![];
+    for file in files {
+        let migration = convert_file_to_migration(&file)?.migration;
+        // Squashed migrations are not part of the chain
+        if migration.replaces.is_empty() {
+            paths.push(file.path());
+            migrations.push(migration);
+        }
+    }
+
+    // Follow the common chain until it forks
+    let mut common: Vec<usize> = vec![];
+    let heads = loop {
+        let next: Vec<usize> = migrations
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| match common.last() {
+                None => x.initial,
+                Some(&last) => x.dependency == Some(migrations[last].id),
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+
+        match next.as_slice() {
+            [] => {
+                if common.len() == migrations.len() {
+                    let message = "Migrations have not diverged - nothing to do.";
+                    report(Event::NothingToDo { message }, message);
+                    return Ok(());
+                }
+                return Err(anyhow!(
+                    "Migrations do not assemble to a coherent list and have not diverged either."
+                ));
+            }
+            [next] => {
+                if common.len() >= migrations.len() {
+                    return Err(anyhow!(
+                        "Found a cycle in the dependencies of the migrations."
+                    ));
+                }
+                common.push(*next);
+            }
+            [a, b] => break [*a, *b],
+            _ => {
+                return Err(anyhow!(
+                    "Found more than two migrations depending on the same migration, \
+                    only two branches can be merged at once."
+                ))
+            }
+        }
+    };
+
+    // Questions can't be answered by programs reading the JSON output
+    let interactive = !non_interactive && output_format() != OutputFormat::Json;
+    let [branch_a, branch_b] = split_branches(&migrations, heads, ask_branch(interactive))?;
+
+    if common.len() + branch_a.len() + branch_b.len() != migrations.len() {
+        return Err(anyhow!(
+            "Migrations outside of the diverged branches found, can not merge."
+        ));
+    }
+
+    let (kept, moved) = match keep {
+        Some(keep) => {
+            let contains = |branch: &[usize]| {
+                branch
+                    .iter()
+                    .any(|x| format!("{:04}_{}", migrations[*x].id, migrations[*x].name) == keep)
+            };
+            if contains(&branch_a) {
+                (branch_a, branch_b)
+            } else if contains(&branch_b) {
+                (branch_b, branch_a)
+            } else {
+                return Err(anyhow!(
+                    "Migration {keep} is not part of the diverged branches."
+                ));
+            }
+        }
+        // Keep the longer branch, as less migrations have to be moved
+        None if branch_b.len() > branch_a.len() => (branch_b, branch_a),
+        None => (branch_a, branch_b),
+    };
+
+    let moved_operations: Vec<Operation> = moved
+        .iter()
+        .flat_map(|x| migrations[*x].operations.iter().cloned())
+        .collect();
+    let kept_operations: Vec<Operation> = kept
+        .iter()
+        .flat_map(|x| migrations[*x].operations.iter().cloned())
+        .collect();
+
+    let conflicts = Touched::from_operations(&kept_operations)?
+        .conflicts(&Touched::from_operations(&moved_operations)?);
+    if !conflicts.is_empty() {
+        return Err(anyhow!(
+            "Both branches change the following, resolve the conflicts manually:\n- {}",
+            conflicts.join("\n- ")
+        ));
+    }
+
+    check_not_applied(
+        &moved.iter().map(|x| &migrations[*x]).collect::<Vec<_>>(),
+        database_config.as_str(),
+        skip_db_check,
+        log_sql,
+    )
+    .await?;
+
+    let mut atomic = true;
+    for idx in &moved {
+        atomic &= is_atomic(&migration_dir, &migrations[*idx])?;
+    }
+
+    let head = &migrations[kept[kept.len() - 1]];
+    let mut merge_migration = Migration {
+        hash: "".to_string(),
+        initial: false,
+        id: head.id + 1,
+        name: format!(
+            "merge_{}",
+            moved
+                .iter()
+                .map(|x| migrations[*x].name.as_str())
+                .collect::<Vec<&str>>()
+                .join("_")
+        ),
+        dependency: Some(head.id),
+        replaces: vec![],
+        operations: moved_operations,
+    };
+
+    let mut chain: Vec<Migration> = common
+        .iter()
+        .chain(kept.iter())
+        .map(|x| migrations[*x].clone())
+        .collect();
+    chain.push(merge_migration.clone());
+
+    print_warning!(
+        "Migrations {} are removed and moved into migration {:04}_{}. \
+        Databases that applied them would mistake the migrations of the kept branch \
+        with the same ids for applied. Make sure the kept branch is the one that has been deployed.",
+        moved
+            .iter()
+            .map(|x| format!("{:04}_{}", migrations[*x].id, migrations[*x].name))
+            .collect::<Vec<_>>()
+            .join(", "),
+        merge_migration.id,
+        merge_migration.name
+    );
+
+    let internal_models = convert_migrations_to_internal_models(&chain)
+        .with_context(|| "Error while replaying the merged migrations")?;
+
+    let mut hasher = DefaultHasher::new();
+    internal_models.hash(&mut hasher);
+    merge_migration.hash = hasher.finish().to_string();
+
+    let path = p.join(format!(
+        "{:04}_{}.toml",
+        merge_migration.id, &merge_migration.name
+    ));
+    record_checksum(&migration_dir, &merge_migration)?;
+    convert_migration_to_file(merge_migration, &path)
+        .with_context(|| "Error occurred while converting migration to file")?;
+    if !atomic {
+        mark_non_atomic(&path)?;
+    }
+    report(
+        Event::FileWritten {
+            path: &path.display().to_string(),
+        },
+        format!("Written merge migration to {}", path.display()),
+    );
+
+    for idx in moved {
+        let (path, migration) = (&paths[idx], &migrations[idx]);
+        remove_file(path)
+            .with_context(|| format!("Couldn't remove merged migration {:?}", path.file_name()))?;
+        report(
+            Event::FileRemoved {
+                path: &path.display().to_string(),
+            },
+            format!(
+                "Removed migration {:04}_{} as it is part of the merge migration",
+                migration.id, migration.name
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rorm_declaration::imr::{Annotation, DbType, Field, ForeignKey};
+    use rorm_declaration::migration::{Migration, Operation};
+
+    use crate::merge_migrations::{ask_branch, split_branches, Touched};
+
+    fn field(name: &str, annotations: Vec<Annotation>) -> Field {
+        Field {
+            name: name.to_string(),
+            db_type: DbType::Int64,
+            annotations,
+            source_defined_at: None,
+        }
+    }
+
+    fn migration(id: u16, name: &str) -> Migration {
+        Migration {
+            hash: "".to_string(),
+            initial: id == 1,
+            id,
+            name: name.to_string(),
+            dependency: (id > 1).then_some(id - 1),
+            replaces: vec![],
+            operations: vec![],
+        }
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let touched = |operations: Vec<Operation>| Touched::from_operations(&operations).unwrap();
+
+        let create_foo = touched(vec![Operation::CreateModel {
+            name: "foo".to_string(),
+            fields: vec![field("id", vec![Annotation::PrimaryKey])],
+        }]);
+        let create_bar = touched(vec![Operation::CreateField {
+            model: "bar".to_string(),
+            field: field("baz", vec![]),
+        }]);
+        let reference_foo = touched(vec![Operation::CreateField {
+            model: "bar".to_string(),
+            field: field(
+                "foo",
+                vec![Annotation::ForeignKey(ForeignKey {
+                    table_name: "foo".to_string(),
+                    column_name: "id".to_string(),
+                    ..Default::default()
+                })],
+            ),
+        }]);
+        let delete_baz = touched(vec![Operation::DeleteField {
+            model: "bar".to_string(),
+            name: "baz".to_string(),
+        }]);
+
+        assert!(create_foo.conflicts(&create_bar).is_empty());
+        assert_eq!(create_foo.conflicts(&reference_foo), vec!["model foo"]);
+        assert_eq!(reference_foo.conflicts(&create_foo), vec!["model foo"]);
+        assert_eq!(
+            create_bar.conflicts(&delete_baz),
+            vec!["field baz of model bar"]
+        );
+    }
+
+    #[test]
+    fn test_split_branches() {
+        // Branch a: 0002_a, 0003_a2, branch b: 0002_b
+        let migrations = vec![
+            migration(1, "initial"),
+            migration(2, "a"),
+            migration(2, "b"),
+            migration(3, "a2"),
+        ];
+        let branches = split_branches(&migrations, [1, 2], |x, first, _| {
+            assert_eq!(x.name, "a2");
+            Ok(first.name == "a")
+        })
+        .unwrap();
+        assert_eq!(branches, [vec![1, 3], vec![2]]);
+        // Without interaction ambiguous branches are an error instead of a question
+        assert!(split_branches(&migrations, [1, 2], ask_branch(false)).is_err());
+
+        // Both branches continue with the same ids
+        let migrations = vec![
+            migration(1, "initial"),
+            migration(2, "a"),
+            migration(2, "b"),
+            migration(3, "b2"),
+            migration(3, "a2"),
+            migration(4, "b3"),
+        ];
+        let branches = split_branches(&migrations, [1, 2], |x, first, _| {
+            Ok(x.name.starts_with(first.name.as_str()))
+        })
+        .unwrap();
+        assert_eq!(branches, [vec![1, 4], vec![2, 3, 5]]);
+
+        let migrations = vec![
+            migration(1, "initial"),
+            migration(2, "a"),
+            migration(2, "b"),
+            migration(3, "a2"),
+            migration(3, "b2"),
+            migration(3, "c2"),
+        ];
+        assert!(split_branches(&migrations, [1, 2], |_, _, _| Ok(true)).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use rorm_db::executor::{Executor, Nothing};
+use rorm_db::Database;
+use rorm_declaration::config::DatabaseConfig;
+use rorm_declaration::migration::Migration;
+use rorm_sql::insert::Insert;
+use rorm_sql::value::Value;
+
+use crate::migrate::lock::{acquire_migration_lock, release_migration_lock};
+use crate::migrate::{
+    connect_database, create_last_migration_table, get_applied_migrations, history_row,
+    last_migration_table_name,
+};
+use crate::utils::migrations::{get_all_existing_migrations, migration_checksum};
+use crate::utils::output::{print_sql, report, Event};
+use crate::{log_sql, print_message};
+
+/// Records a migration as applied without executing it
+///
+/// If `unapply` is set, the record of the migration is removed instead,
+/// again without executing anything.
+pub async fn run_fake_custom(
+    db_conf: DatabaseConfig,
+    migration_dir: String,
+    log_sql: bool,
+    migration_id: u16,
+    unapply: bool,
+    lock_timeout: Duration,
+) -> anyhow::Result<()> {
+    let p = Path::new(migration_dir.as_str());
+    if !p.exists() || p.is_file() {
+        print_message!(
+            "Couldn't find the migration directory in {} \n\n\
+            You can specify an alternative path with --migration-dir <PATH>",
+            migration_dir.as_str()
+        );
+        return Ok(());
+    }
+
+    let existing_migrations = get_all_existing_migrations(migration_dir.as_str())
+        .with_context(|| "Couldn't retrieve existing migrations")?;
+
+    let pool = connect_database(&db_conf).await?;
+    let last_migration_table_name = last_migration_table_name(&db_conf);
+
+    acquire_migration_lock(&pool, last_migration_table_name, lock_timeout, log_sql).await?;
+    let result = record_fake_migration(
+        &pool,
+        &db_conf,
+        &existing_migrations,
+        migration_id,
+        unapply,
+        log_sql,
+    )
+    .await;
+    let released = release_migration_lock(&pool, last_migration_table_name, log_sql).await;
+
+    // A failed release must not hide the error of the migration
+    result.and(released)
+}
+
+/// Records or removes the record of a migration, must be called while holding the migration lock
+async fn record_fake_migration(
+    pool: &Database,
+    db_conf: &DatabaseConfig,
+    existing_migrations: &[Migration],
+    migration_id: u16,
+    unapply: bool,
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    create_last_migration_table(pool, db_conf, log_sql).await?;
+    let last_migration_table_name = last_migration_table_name(db_conf);
+    let db_impl = pool.dialect();
+
+    let applied_migrations =
+        get_applied_migrations(pool, last_migration_table_name, log_sql).await?;
+    let applied = applied_migrations
+        .iter()
+        .any(|x| x.id == migration_id && x.applied_operations.is_none());
+    let partially_applied = applied_migrations
+        .iter()
+        .any(|x| x.id == migration_id && x.applied_operations.is_some());
+
+    if unapply {
+        if !applied && !partially_applied {
+            let message =
+                format!("Migration {migration_id:04} is not recorded as applied - nothing to do.");
+            report(Event::NothingToDo { message: &message }, &message);
+            return Ok(());
+        }
+
+        pool.execute::<Nothing>(
+            log_sql!(
+                format!(
+                    "DELETE FROM {last_migration_table_name} WHERE migration_id = {migration_id};"
+                ),
+                log_sql
+            ),
+            Vec::new(),
+        )
+        .await
+        .with_context(|| {
+            format!("Error while removing migration {migration_id} from last migration table")
+        })?;
+
+        report(
+            Event::MigrationUnrecorded { id: migration_id },
+            format!("Removed the record of migration {migration_id:04} without reverting it"),
+        );
+        return Ok(());
+    }
+
+    // Unknown migrations can only be unapplied
+    let migration = existing_migrations
+        .iter()
+        .find(|x| x.id == migration_id)
+        .ok_or_else(|| anyhow!("Migration {migration_id:04} was not found."))?;
+
+    if applied {
+        let message =
+            format!("Migration {migration_id:04} is already recorded as applied - nothing to do.");
+        report(Event::NothingToDo { message: &message }, &message);
+        return Ok(());
+    }
+
+    // The record of a partially applied migration is replaced
+    if partially_applied {
+        pool.execute::<Nothing>(
+            log_sql!(
+                format!(
+                    "DELETE FROM {last_migration_table_name} WHERE migration_id = {migration_id} AND applied_operations IS NOT NULL;"
+                ),
+                log_sql
+            ),
+            Vec::new(),
+        )
+        .await
+        .with_context(|| {
+            format!("Error while removing the progress of migration {migration_id} from last migration table")
+        })?;
+    }
+
+    let checksum = migration_checksum(migration)?;
+    let (columns, row) = history_row(migration, &checksum, None);
+    let v: &[&[Value]] = &[&row];
+    let (query_string, bind_params) = db_impl
+        .insert(last_migration_table_name, &columns, v, None)
+        .build();
+
+    if log_sql {
+        print_sql(&query_string);
+    }
+
+    pool.execute::<Nothing>(query_string, bind_params)
+        .await
+        .with_context(|| {
+            format!("Error while inserting migration {migration_id} into last migration table")
+        })?;
+
+    report(
+        Event::MigrationRecorded {
+            id: migration.id,
+            name: migration.name.as_str(),
+        },
+        format!(
+            "Recorded migration {:04}_{} as applied without running it",
+            migration.id, migration.name
+        ),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use rorm_db::executor::{Executor, Nothing, Optional};
+use rorm_db::Database;
+use rorm_sql::DBImpl;
+
+use crate::log_sql;
+
+/// Default time to wait for the migration lock
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Helper method to take the lock that prevents concurrent migrations.
+///
+/// The lock is bound to the connection of `db`, so it is released
+/// at the latest when the connection is closed.
+///
+/// - `db`: [`&Database`](Database): Database to lock, must consist of a single connection.
+/// - `last_migration_table_name`: [`&str`]: Name of the last migration table, used as name of the lock.
+/// - `timeout`: [`Duration`]: Time to wait for the lock.
+/// - `log_sql`: [`bool`]: Log all SQL statements.
+pub async fn acquire_migration_lock(
+    db: &Database,
+    last_migration_table_name: &str,
+    timeout: Duration,
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    let err = || {
+        format!(
+            "Couldn't acquire the migration lock within {} seconds, is another migration running?",
+            timeout.as_secs()
+        )
+    };
+
+    match db.dialect() {
+        #[cfg(feature = "sqlite")]
+        DBImpl::SQLite => {
+            // Once written, an exclusive lock is kept until the locking mode is reset
+            for query in [
+                format!("PRAGMA busy_timeout = {};", timeout.as_millis()),
+                "PRAGMA locking_mode = EXCLUSIVE;".to_string(),
+                "BEGIN EXCLUSIVE;".to_string(),
+                "COMMIT;".to_string(),
+            ] {
+                db.execute::<Nothing>(log_sql!(query, log_sql), Vec::new())
+                    .await
+                    .with_context(err)?;
+            }
+        }
+        #[cfg(feature = "mysql")]
+        DBImpl::MySQL => {
+            let acquired: Option<Option<i64>> = db
+                .execute::<Optional>(
+                    log_sql!(
+                        format!(
+                            "SELECT GET_LOCK(CONCAT(DATABASE(), '.{last_migration_table_name}'), {});",
+                            timeout.as_secs()
+                        ),
+                        log_sql
+                    ),
+                    Vec::new(),
+                )
+                .await
+                .and_then(|option| option.map(|row| row.get(0)).transpose())
+                .with_context(err)?;
+
+            // GET_LOCK returns 0 on timeout and NULL on errors
+            if acquired.flatten() != Some(1) {
+                return Err(anyhow!(err()));
+            }
+        }
+        #[cfg(feature = "postgres")]
+        DBImpl::Postgres => {
+            for query in [
+                format!("SET lock_timeout = {};", timeout.as_millis()),
+                format!("SELECT pg_advisory_lock(hashtext('{last_migration_table_name}'));"),
+                "RESET lock_timeout;".to_string(),
+            ] {
+                db.execute::<Nothing>(log_sql!(query, log_sql), Vec::new())
+                    .await
+                    .with_context(err)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Helper method to release the lock taken by [acquire_migration_lock].
+///
+/// - `db`: [`&Database`](Database): Database the lock was taken on.
+/// - `last_migration_table_name`: [`&str`]: Name of the last migration table, used as name of the lock.
+/// - `log_sql`: [`bool`]: Log all SQL statements.
+pub async fn release_migration_lock(
+    db: &Database,
+    last_migration_table_name: &str,
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    let queries = match db.dialect() {
+        // The lock is released with the next access after resetting the locking mode
+        #[cfg(feature = "sqlite")]
+        DBImpl::SQLite => vec![
+            "PRAGMA locking_mode = NORMAL;".to_string(),
+            "SELECT 1 FROM sqlite_master LIMIT 1;".to_string(),
+        ],
+        #[cfg(feature = "mysql")]
+        DBImpl::MySQL => vec![format!(
+            "SELECT RELEASE_LOCK(CONCAT(DATABASE(), '.{last_migration_table_name}'));"
+        )],
+        #[cfg(feature = "postgres")]
+        DBImpl::Postgres => vec![format!(
+            "SELECT pg_advisory_unlock(hashtext('{last_migration_table_name}'));"
+        )],
+    };
+
+    for query in queries {
+        db.execute::<Nothing>(log_sql!(query, log_sql), Vec::new())
+            .await
+            .with_context(|| "Couldn't release the migration lock")?;
+    }
+
+    Ok(())
+}
@@ -1,22 +1,42 @@
-use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::path::Path;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context};
-use rorm_db::executor::{Executor, Nothing, Optional};
+use rorm_db::executor::{All, Executor, Nothing, Optional};
+use rorm_db::transaction::Transaction;
 use rorm_db::Database;
 use rorm_declaration::config::DatabaseConfig;
-use rorm_declaration::imr::{Annotation, DbType};
+use rorm_declaration::imr::{Annotation, DbType, Model};
 use rorm_declaration::migration::Migration;
+use rorm_sql::alter_table::{AlterTable, AlterTableOperation};
 use rorm_sql::create_table::CreateTable;
 use rorm_sql::insert::Insert;
+use rorm_sql::value::Value;
 use rorm_sql::DBImpl;
 
-use crate::log_sql;
-use crate::migrate::config::{create_db_config, deserialize_db_conf};
-use crate::migrate::sql_builder::migration_to_sql;
-use crate::utils::migrations::get_existing_migrations;
+use crate::migrate::config::{create_db_config, database_url, deserialize_db_conf};
+use crate::migrate::fake::run_fake_custom;
+use crate::migrate::lock::{acquire_migration_lock, release_migration_lock, DEFAULT_LOCK_TIMEOUT};
+use crate::migrate::rollback::run_rollback_custom;
+use crate::migrate::sql_builder::{
+    check_non_atomic_migration, check_sqlite_foreign_keys, dialect_of_driver, migration_to_sql,
+    operation_to_statements, print_migration_sql, rebuilds_sqlite_table,
+    with_sqlite_foreign_keys_disabled,
+};
+use crate::utils::introspection::get_column_names;
+use crate::utils::migrations::{
+    apply_migration_to_state, apply_operation_to_state, convert_migrations_to_internal_models,
+    get_all_existing_migrations, get_existing_migrations, is_atomic, migration_checksum,
+    substitute_squashed_migrations,
+};
+use crate::utils::output::{print_sql, report, Event};
+use crate::{log_sql, print_message, print_warning};
 
 pub mod config;
+pub mod fake;
+pub mod lock;
+pub mod rollback;
 pub mod sql_builder;
 
 /// Options for running migrations
@@ -32,40 +52,169 @@ pub struct MigrateOptions {
 
     /// Apply only to (inclusive) the given id, if set
     pub apply_until: Option<u16>,
+
+    /// Revert all migrations after the given id, if set
+    pub rollback_to: Option<u16>,
+
+    /// Print the SQL statements instead of applying them
+    pub dry_run: bool,
+
+    /// Assume the migrations before the given id have been applied in a dry run, if set
+    pub dry_run_from: Option<u16>,
+
+    /// Record the given id as applied without running it, if set
+    pub fake: Option<u16>,
+
+    /// Remove the record of the given id without reverting it, if set
+    pub fake_unapply: Option<u16>,
+
+    /// Ids of applied migrations whose changes are accepted
+    pub accept_changed: Vec<u16>,
+
+    /// Time to wait for other migrations to finish
+    pub lock_timeout: Duration,
+
+    /// Apply all pending migrations in a single transaction
+    pub atomic: bool,
 }
 
-/// Helper method to apply one migration. Writes also to last migration table.
+impl Default for MigrateOptions {
+    fn default() -> Self {
+        Self {
+            migration_dir: String::from("./migrations/"),
+            database_config: String::from("./database.toml"),
+            log_queries: false,
+            apply_until: None,
+            rollback_to: None,
+            dry_run: false,
+            dry_run_from: None,
+            fake: None,
+            fake_unapply: None,
+            accept_changed: vec![],
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            atomic: false,
+        }
+    }
+}
+
+/// Options for [run_migrate_custom_with_options]
+pub struct MigrateCustomOptions {
+    /// Directory, migrations exist in
+    pub migration_dir: String,
+
+    /// Log all SQL statements
+    pub log_queries: bool,
+
+    /// Apply only to (inclusive) the given id, if set
+    pub apply_until: Option<u16>,
+
+    /// Ids of applied migrations whose changes are accepted
+    pub accept_changed: Vec<u16>,
+
+    /// Time to wait for other migrations to finish
+    pub lock_timeout: Duration,
+
+    /// Apply all pending migrations in a single transaction
+    pub atomic: bool,
+}
+
+impl Default for MigrateCustomOptions {
+    fn default() -> Self {
+        Self {
+            migration_dir: String::from("./migrations/"),
+            log_queries: false,
+            apply_until: None,
+            accept_changed: vec![],
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            atomic: false,
+        }
+    }
+}
+
+/// Columns of the last migration table that record the history of applied migrations
+const HISTORY_TABLE_COLUMNS: [(&str, DbType); 6] = [
+    ("migration_name", DbType::VarChar),
+    ("migration_hash", DbType::VarChar),
+    ("checksum", DbType::VarChar),
+    ("duration_ms", DbType::Int64),
+    ("rorm_cli_version", DbType::VarChar),
+    ("applied_operations", DbType::Int32),
+];
+
+fn history_column_annotations(db_type: DbType) -> Vec<Annotation> {
+    match db_type {
+        DbType::VarChar => vec![Annotation::MaxLength(255)],
+        _ => vec![],
+    }
+}
+
+/// Helper method to create the row that records an applied migration in the last migration table.
+///
+/// Returns the columns and the values of the row.
+///
+/// - `migration`: [`&Migration`](Migration): Reference to the applied migration.
+/// - `checksum`: [`&str`]: Checksum of the migration, see [migration_checksum].
+/// - `duration_ms`: [`Option<i64>`]: Time it took to apply the migration, if it was executed.
+pub fn history_row<'a>(
+    migration: &'a Migration,
+    checksum: &'a str,
+    duration_ms: Option<i64>,
+) -> (Vec<&'static str>, Vec<Value<'a>>) {
+    let mut columns = vec![
+        "migration_id",
+        "migration_name",
+        "migration_hash",
+        "checksum",
+        "rorm_cli_version",
+    ];
+    let mut values = vec![
+        Value::I32(migration.id as i32),
+        Value::String(migration.name.as_str()),
+        Value::String(migration.hash.as_str()),
+        Value::String(checksum),
+        Value::String(env!("CARGO_PKG_VERSION")),
+    ];
+
+    if let Some(duration_ms) = duration_ms {
+        columns.push("duration_ms");
+        values.push(Value::I64(duration_ms));
+    }
+
+    (columns, values)
+}
+
+/// Helper method to execute one migration and record it in the last migration table
+/// within the given transaction.
 ///
+/// - `tx`: [`&mut Transaction`](Transaction): Transaction to execute the migration in.
 /// - `migration`: [`&Migration`](Migration): Reference to the migration to apply.
-/// - `db`: [`&Database`](Database): Database to apply the migration onto.
+/// - `checksum`: [`&str`]: Checksum of the migration, see [migration_checksum].
+/// - `models`: [`Option<&[Model]>`](Model): The models before the migration, if known.
 /// - `last_migration_table_name`: [`&str`]: Name of the table to insert successful applied migrations into.
-pub async fn apply_migration(
+///
+/// Returns the time it took to execute the migration in milliseconds.
+pub async fn execute_and_record_migration(
+    tx: &mut Transaction,
     dialect: DBImpl,
     migration: &Migration,
-    db: &Database,
+    checksum: &str,
+    models: Option<&[Model]>,
     last_migration_table_name: &str,
     do_log: bool,
-) -> anyhow::Result<()> {
-    let mut tx = db
-        .start_transaction()
-        .await
-        .with_context(|| format!("Error while starting transaction {}", migration.id))?;
-
-    if let Err(e) = migration_to_sql(&mut tx, dialect, migration, do_log).await {
-        tx.rollback()
-            .await
-            .with_context(|| "Error while rollback in transaction")?;
-        return Err(e);
-    }
+) -> anyhow::Result<i64> {
+    let start = Instant::now();
+    migration_to_sql(tx, dialect, migration, models, do_log).await?;
+    let duration_ms = start.elapsed().as_millis() as i64;
 
-    let v: &[&[rorm_sql::value::Value]] = &[&[rorm_sql::value::Value::I32(migration.id as i32)]];
+    let (columns, row) = history_row(migration, checksum, Some(duration_ms));
+    let v: &[&[Value]] = &[&row];
     let (query_string, bind_params) = dialect
-        .insert(last_migration_table_name, &["migration_id"], v, None)
+        .insert(last_migration_table_name, &columns, v, None)
         .rollback_transaction()
         .build();
 
     if do_log {
-        println!("{query_string}");
+        print_sql(&query_string);
     }
 
     tx.execute::<Nothing>(query_string, bind_params).await.with_context(|| {
@@ -74,57 +223,357 @@ pub async fn apply_migration(
         )
     })?;
 
-    println!("Applied migration {:04}_{}", migration.id, migration.name);
+    Ok(duration_ms)
+}
 
-    tx.commit().await.with_context(|| {
-        format!("Error while committing transaction {last_migration_table_name}",)
-    })?;
+fn report_applied_migration(migration: &Migration, duration_ms: i64) {
+    report(
+        Event::MigrationApplied {
+            id: migration.id,
+            name: migration.name.as_str(),
+            duration_ms,
+        },
+        format!("Applied migration {:04}_{}", migration.id, migration.name),
+    );
+}
 
-    Ok(())
+/// Helper method to apply one migration. Writes also to last migration table.
+///
+/// - `migration`: [`&Migration`](Migration): Reference to the migration to apply.
+/// - `checksum`: [`&str`]: Checksum of the migration, see [migration_checksum].
+/// - `models`: [`Option<&[Model]>`](Model): The models before the migration, if known.
+/// - `db`: [`&Database`](Database): Database to apply the migration onto.
+/// - `last_migration_table_name`: [`&str`]: Name of the table to insert successful applied migrations into.
+pub async fn apply_migration(
+    dialect: DBImpl,
+    migration: &Migration,
+    checksum: &str,
+    models: Option<&[Model]>,
+    db: &Database,
+    last_migration_table_name: &str,
+    do_log: bool,
+) -> anyhow::Result<()> {
+    let rebuild = rebuilds_sqlite_table(dialect, migration, models);
+
+    with_sqlite_foreign_keys_disabled(db, rebuild, do_log, async {
+        let mut tx = db
+            .start_transaction()
+            .await
+            .with_context(|| format!("Error while starting transaction {}", migration.id))?;
+
+        let result = match execute_and_record_migration(
+            &mut tx,
+            dialect,
+            migration,
+            checksum,
+            models,
+            last_migration_table_name,
+            do_log,
+        )
+        .await
+        {
+            Ok(duration_ms) if rebuild => check_sqlite_foreign_keys(&mut tx, do_log)
+                .await
+                .map(|_| duration_ms),
+            result => result,
+        };
+        let duration_ms = match result {
+            Ok(duration_ms) => duration_ms,
+            Err(e) => {
+                tx.rollback()
+                    .await
+                    .with_context(|| "Error while rollback in transaction")?;
+                return Err(e);
+            }
+        };
+
+        report_applied_migration(migration, duration_ms);
+
+        tx.commit().await.with_context(|| {
+            format!("Error while committing transaction {last_migration_table_name}",)
+        })?;
+
+        Ok(())
+    })
+    .await
 }
 
-/// Applies migrations on the given database with a given driver
-pub async fn run_migrate_custom(
-    db_conf: DatabaseConfig,
-    migration_dir: String,
-    log_sql: bool,
-    apply_until: Option<u16>,
+/// Helper method to apply one migration without a transaction. Writes also to last migration table.
+///
+/// Every statement is executed on its own. The number of applied operations is recorded
+/// after each operation, so a failed migration is resumed with the next run.
+///
+/// - `migration`: [`&Migration`](Migration): Reference to the migration to apply.
+/// - `checksum`: [`&str`]: Checksum of the migration, see [migration_checksum].
+/// - `applied_operations`: [`Option<u16>`]: Number of operations applied by a previous run, if any.
+/// - `models`: [`Option<&[Model]>`](Model): The models before the migration, if known.
+/// - `db`: [`&Database`](Database): Database to apply the migration onto.
+/// - `last_migration_table_name`: [`&str`]: Name of the table to insert successful applied migrations into.
+#[allow(clippy::too_many_arguments)]
+pub async fn apply_migration_non_atomic(
+    dialect: DBImpl,
+    migration: &Migration,
+    checksum: &str,
+    applied_operations: Option<u16>,
+    models: Option<&[Model]>,
+    db: &Database,
+    last_migration_table_name: &str,
+    do_log: bool,
 ) -> anyhow::Result<()> {
-    let p = Path::new(migration_dir.as_str());
-    if !p.exists() || p.is_file() {
-        println!(
-            "Couldn't find the migration directory in {} \n\n\
-            You can specify an alternative path with --migration-dir <PATH>",
-            migration_dir.as_str()
-        );
-        return Ok(());
+    check_non_atomic_migration(dialect, migration, models)?;
+    let start = Instant::now();
+
+    let applied_operations = match applied_operations {
+        Some(applied_operations) => {
+            print_message!(
+                "Resuming migration {:04}_{} after {applied_operations} of {} operations",
+                migration.id,
+                migration.name,
+                migration.operations.len()
+            );
+            applied_operations as usize
+        }
+        None => {
+            let (mut columns, mut row) = history_row(migration, checksum, None);
+            columns.push("applied_operations");
+            row.push(Value::I32(0));
+            let v: &[&[Value]] = &[&row];
+            let (query_string, bind_params) = dialect
+                .insert(last_migration_table_name, &columns, v, None)
+                .build();
+
+            if do_log {
+                print_sql(&query_string);
+            }
+
+            db.execute::<Nothing>(query_string, bind_params)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Error while inserting migration {} into last migration table",
+                        migration.id
+                    )
+                })?;
+            0
+        }
+    };
+
+    let mut models = models.map(<[Model]>::to_vec);
+    for (idx, operation) in migration.operations.iter().enumerate() {
+        let before = models.clone();
+        apply_operation_to_state(&mut models, operation);
+        if idx < applied_operations {
+            continue;
+        }
+
+        for (query_string, bind_params) in
+            operation_to_statements(dialect, operation, before.as_deref())?
+        {
+            if do_log {
+                print_sql(&query_string);
+            }
+
+            db.execute::<Nothing>(query_string, bind_params)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Operation {} of migration {:04}_{} failed, {idx} of {} operations have been applied.\n\n\
+                        Fix the cause and run migrate again to resume the migration.",
+                        idx + 1,
+                        migration.id,
+                        migration.name,
+                        migration.operations.len()
+                    )
+                })?;
+        }
+
+        db.execute::<Nothing>(
+            log_sql!(
+                format!(
+                    "UPDATE {last_migration_table_name} SET applied_operations = {} WHERE migration_id = {} AND applied_operations IS NOT NULL;",
+                    idx + 1,
+                    migration.id
+                ),
+                do_log
+            ),
+            Vec::new(),
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "Error while recording the progress of migration {}",
+                migration.id
+            )
+        })?;
     }
 
-    let existing_migrations = get_existing_migrations(migration_dir.as_str())
-        .with_context(|| "Couldn't retrieve existing migrations")?;
+    let duration_ms = start.elapsed().as_millis() as i64;
+    db.execute::<Nothing>(
+        log_sql!(
+            format!(
+                "UPDATE {last_migration_table_name} SET applied_operations = NULL, duration_ms = {duration_ms} WHERE migration_id = {} AND applied_operations IS NOT NULL;",
+                migration.id
+            ),
+            do_log
+        ),
+        Vec::new(),
+    )
+    .await
+    .with_context(|| format!("Error while completing migration {}", migration.id))?;
 
-    if existing_migrations.is_empty() {
-        println!("No migrations found.\nExiting.");
-        return Ok(());
+    report_applied_migration(migration, duration_ms);
+
+    Ok(())
+}
+
+/// Helper method to apply several migrations in a single transaction.
+///
+/// If one migration fails, none of the migrations is applied.
+///
+/// - `migrations`: [`&[&Migration]`](Migration): The migrations to apply in order.
+/// - `models`: [`Option<&[Model]>`](Model): The models before the first migration, if known.
+/// - `db`: [`&Database`](Database): Database to apply the migrations onto.
+/// - `last_migration_table_name`: [`&str`]: Name of the table to insert successful applied migrations into.
+pub async fn apply_migrations_atomic(
+    dialect: DBImpl,
+    migrations: &[&Migration],
+    models: Option<&[Model]>,
+    db: &Database,
+    last_migration_table_name: &str,
+    do_log: bool,
+) -> anyhow::Result<()> {
+    let mut state = models.map(<[Model]>::to_vec);
+    let mut rebuild = false;
+    for migration in migrations {
+        rebuild |= rebuilds_sqlite_table(dialect, migration, state.as_deref());
+        apply_migration_to_state(&mut state, migration);
     }
 
-    let pool = Database::connect(rorm_db::DatabaseConfiguration {
-        driver: db_conf.driver,
+    with_sqlite_foreign_keys_disabled(db, rebuild, do_log, async {
+        let mut tx = db
+            .start_transaction()
+            .await
+            .with_context(|| "Error while starting transaction")?;
+
+        let mut models = models.map(<[Model]>::to_vec);
+        let mut durations = vec![];
+        for migration in migrations {
+            let result = match migration_checksum(migration) {
+                Ok(checksum) => {
+                    execute_and_record_migration(
+                        &mut tx,
+                        dialect,
+                        migration,
+                        &checksum,
+                        models.as_deref(),
+                        last_migration_table_name,
+                        do_log,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            apply_migration_to_state(&mut models, migration);
+
+            match result {
+                Ok(duration_ms) => durations.push(duration_ms),
+                Err(e) => {
+                    tx.rollback()
+                        .await
+                        .with_context(|| "Error while rollback in transaction")?;
+                    return Err(e.context(format!(
+                        "Migration {:04}_{} failed, no migration has been applied",
+                        migration.id, migration.name
+                    )));
+                }
+            }
+        }
+
+        if rebuild {
+            if let Err(e) = check_sqlite_foreign_keys(&mut tx, do_log).await {
+                tx.rollback()
+                    .await
+                    .with_context(|| "Error while rollback in transaction")?;
+                return Err(e.context("No migration has been applied"));
+            }
+        }
+
+        tx.commit()
+            .await
+            .with_context(|| "Error while committing transaction")?;
+
+        for (migration, duration_ms) in migrations.iter().zip(durations) {
+            report_applied_migration(migration, duration_ms);
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+/// Helper method to retrieve the name of the last migration table.
+///
+/// - `db_conf`: [`&DatabaseConfig`](DatabaseConfig): The configuration of the database.
+pub fn last_migration_table_name(db_conf: &DatabaseConfig) -> &str {
+    db_conf
+        .last_migration_table_name
+        .as_ref()
+        .map_or("_rorm__last_migration", |x| x.as_str())
+}
+
+/// Helper method to connect to the database.
+///
+/// Only a single connection is opened, so session level locks hold for all statements.
+///
+/// - `db_conf`: [`&DatabaseConfig`](DatabaseConfig): The configuration of the database.
+pub async fn connect_database(db_conf: &DatabaseConfig) -> anyhow::Result<Database> {
+    Ok(Database::connect(rorm_db::DatabaseConfiguration {
+        driver: db_conf.driver.clone(),
         min_connections: 1,
         max_connections: 1,
         disable_logging: None,
         statement_log_level: None,
         slow_statement_log_level: None,
     })
-    .await?;
+    .await?)
+}
 
-    let last_migration_table_name = db_conf
-        .last_migration_table_name
-        .as_ref()
-        .map_or("_rorm__last_migration", |x| x.as_str());
+/// Helper method to connect to the database and create the last migration table
+/// if it does not exist yet.
+///
+/// - `db_conf`: [`&DatabaseConfig`](DatabaseConfig): The configuration of the database.
+/// - `log_sql`: [`bool`]: Log all SQL statements.
+pub async fn connect_and_create_last_migration_table(
+    db_conf: &DatabaseConfig,
+    log_sql: bool,
+) -> anyhow::Result<Database> {
+    let pool = connect_database(db_conf).await?;
+    create_last_migration_table(&pool, db_conf, log_sql).await?;
+
+    Ok(pool)
+}
 
-    let db_impl = (&pool).dialect();
-    let statements = db_impl
+/// Helper method to create the last migration table if it does not exist yet.
+///
+/// Tables created by older versions are extended by the columns recording the history.
+///
+/// - `pool`: [`&Database`](Database): Database to create the table in.
+/// - `db_conf`: [`&DatabaseConfig`](DatabaseConfig): The configuration of the database.
+/// - `log_sql`: [`bool`]: Log all SQL statements.
+pub async fn create_last_migration_table(
+    pool: &Database,
+    db_conf: &DatabaseConfig,
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    let last_migration_table_name = last_migration_table_name(db_conf);
+
+    let db_impl = pool.dialect();
+    let history_annotations: Vec<Vec<Annotation>> = HISTORY_TABLE_COLUMNS
+        .iter()
+        .map(|(_, db_type)| history_column_annotations(*db_type))
+        .collect();
+
+    let mut create_table = db_impl
         .create_table(last_migration_table_name)
         .add_column(db_impl.create_column(
             last_migration_table_name,
@@ -143,9 +592,16 @@ pub async fn run_migrate_custom(
             "migration_id",
             DbType::Int32,
             &[Annotation::NotNull],
-        ))
-        .if_not_exists()
-        .build()?;
+        ));
+    for ((name, db_type), annotations) in HISTORY_TABLE_COLUMNS.iter().zip(&history_annotations) {
+        create_table = create_table.add_column(db_impl.create_column(
+            last_migration_table_name,
+            name,
+            *db_type,
+            annotations,
+        ));
+    }
+    let statements = create_table.if_not_exists().build()?;
 
     let mut tx = pool
         .start_transaction()
@@ -154,7 +610,7 @@ pub async fn run_migrate_custom(
 
     for (query_string, bind_params) in statements {
         if log_sql {
-            println!("{}", query_string.as_str());
+            print_sql(&query_string);
         }
 
         tx.execute::<Nothing>(query_string, bind_params)
@@ -166,7 +622,52 @@ pub async fn run_migrate_custom(
         .await
         .with_context(|| "Couldn't create internal last migration table")?;
 
-    let last_migration: Option<i32> = pool
+    // Tables created by older versions lack the columns recording the history
+    let existing_columns = get_column_names(pool, last_migration_table_name, log_sql).await?;
+    for ((name, db_type), annotations) in HISTORY_TABLE_COLUMNS.iter().zip(&history_annotations) {
+        if existing_columns.iter().any(|x| x == name) {
+            continue;
+        }
+
+        let statements = db_impl
+            .alter_table(
+                last_migration_table_name,
+                AlterTableOperation::AddColumn {
+                    operation: db_impl.create_column(
+                        last_migration_table_name,
+                        name,
+                        *db_type,
+                        annotations,
+                    ),
+                },
+            )
+            .build()?;
+
+        for (query_string, bind_params) in statements {
+            if log_sql {
+                print_sql(&query_string);
+            }
+
+            pool.execute::<Nothing>(query_string, bind_params)
+                .await
+                .with_context(|| format!("Couldn't add column {name} to last migration table"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Helper method to retrieve the id of the last applied migration.
+///
+/// - `db`: [`&Database`](Database): Database to query.
+/// - `last_migration_table_name`: [`&str`]: Name of the table successful applied migrations are inserted into.
+/// - `log_sql`: [`bool`]: Log all SQL statements.
+pub async fn get_last_migration(
+    db: &Database,
+    last_migration_table_name: &str,
+    log_sql: bool,
+) -> anyhow::Result<Option<u16>> {
+    let last_migration: Option<i32> = db
         .execute::<Optional>(
             log_sql!(
                 format!(
@@ -183,84 +684,562 @@ pub async fn run_migrate_custom(
             "Couldn't fetch information about successful migrations from migration table"
         })?;
 
-    match last_migration {
-        None => {
-            // Apply all migrations
-            for migration in &existing_migrations {
+    Ok(last_migration.map(|x| x as u16))
+}
+
+/// A migration recorded in the last migration table
+#[derive(Debug, Clone)]
+pub struct AppliedMigration {
+    /// Id of the migration
+    pub id: u16,
+
+    /// Name of the migration, unknown for migrations recorded by older versions
+    pub name: Option<String>,
+
+    /// Checksum of the migration file at the time it was applied
+    pub checksum: Option<String>,
+
+    /// Time the migration was applied at
+    pub applied_at: Option<String>,
+
+    /// Number of applied operations of a non-atomic migration that has not been completed yet
+    pub applied_operations: Option<u16>,
+}
+
+/// Helper method to retrieve all applied migrations in the order they were applied in.
+///
+/// - `db`: [`&Database`](Database): Database to query.
+/// - `last_migration_table_name`: [`&str`]: Name of the table successful applied migrations are inserted into.
+/// - `log_sql`: [`bool`]: Log all SQL statements.
+pub async fn get_applied_migrations(
+    db: &Database,
+    last_migration_table_name: &str,
+    log_sql: bool,
+) -> anyhow::Result<Vec<AppliedMigration>> {
+    query_applied_migrations(db, last_migration_table_name, None, log_sql).await
+}
+
+/// Helper method to retrieve all applied migrations without changing the database.
+///
+/// Unlike [create_last_migration_table], neither the table nor missing columns are created,
+/// so it can be used by read-only commands. If the table does not exist, no migrations
+/// have been applied. Columns missing in tables created by older versions are read as `NULL`.
+///
+/// - `db`: [`&Database`](Database): Database to query.
+/// - `last_migration_table_name`: [`&str`]: Name of the table successful applied migrations are inserted into.
+/// - `log_sql`: [`bool`]: Log all SQL statements.
+pub async fn get_applied_migrations_read_only(
+    db: &Database,
+    last_migration_table_name: &str,
+    log_sql: bool,
+) -> anyhow::Result<Vec<AppliedMigration>> {
+    let existing_columns = get_column_names(db, last_migration_table_name, log_sql).await?;
+    if existing_columns.is_empty() {
+        return Ok(vec![]);
+    }
+
+    query_applied_migrations(
+        db,
+        last_migration_table_name,
+        Some(&existing_columns),
+        log_sql,
+    )
+    .await
+}
+
+/// Selects the applied migrations, columns not in `existing_columns` are read as `NULL`
+async fn query_applied_migrations(
+    db: &Database,
+    last_migration_table_name: &str,
+    existing_columns: Option<&[String]>,
+    log_sql: bool,
+) -> anyhow::Result<Vec<AppliedMigration>> {
+    let column = |name: &str| match existing_columns {
+        Some(columns) if !columns.iter().any(|x| x == name) => "NULL".to_string(),
+        _ => name.to_string(),
+    };
+
+    let text_type = match db.dialect() {
+        #[cfg(feature = "sqlite")]
+        DBImpl::SQLite => "TEXT",
+        #[cfg(feature = "mysql")]
+        DBImpl::MySQL => "CHAR",
+        #[cfg(feature = "postgres")]
+        DBImpl::Postgres => "TEXT",
+    };
+
+    let rows = db
+        .execute::<All>(
+            log_sql!(
+                format!(
+                    "SELECT migration_id, {}, {}, CAST(updated_at AS {text_type}), {} FROM {last_migration_table_name} ORDER BY id ASC;",
+                    column("migration_name"),
+                    column("checksum"),
+                    column("applied_operations"),
+                ),
+                log_sql
+            ),
+            Vec::new(),
+        )
+        .await
+        .with_context(|| {
+            "Couldn't fetch information about successful migrations from migration table"
+        })?;
+
+    let mut applied = vec![];
+    for row in rows {
+        let migration_id: i32 = row.get(0)?;
+        let name: Option<String> = row.get(1)?;
+        let checksum: Option<String> = row.get(2)?;
+        let applied_at: Option<String> = row.get(3)?;
+        let applied_operations: Option<i32> = row.get(4)?;
+        applied.push(AppliedMigration {
+            id: migration_id as u16,
+            name,
+            checksum,
+            applied_at,
+            applied_operations: applied_operations.map(|x| x as u16),
+        });
+    }
+
+    Ok(applied)
+}
+
+/// Helper method to determine the ids of all applied migrations.
+///
+/// Applying a squashed migration counts as applying all migrations it replaces.
+/// Non-atomic migrations that have not been completed yet are not included.
+///
+/// - `applied`: [`&[AppliedMigration]`](AppliedMigration): The recorded migrations, see [get_applied_migrations].
+/// - `migrations`: [`&[Migration]`](Migration): All migrations including squashed ones.
+pub fn applied_migration_ids(
+    applied: &[AppliedMigration],
+    migrations: &[Migration],
+) -> HashSet<u16> {
+    let mut ids = HashSet::new();
+
+    for row in applied.iter().filter(|x| x.applied_operations.is_none()) {
+        ids.insert(row.id);
+
+        // Squashed migrations share their id with the last migration they replace
+        for squash in migrations
+            .iter()
+            .filter(|x| x.id == row.id && !x.replaces.is_empty())
+        {
+            if row.name.is_none() || row.name.as_ref() == Some(&squash.name) {
+                ids.extend(squash.replaces.iter().copied());
+            }
+        }
+    }
+
+    ids
+}
+
+/// Helper method to find applied migrations that were changed since they were applied
+///
+/// Returns the changed migrations with their current checksum.
+/// Migrations recorded without a checksum are skipped.
+///
+/// - `migrations`: [`&[Migration]`](Migration): The migrations to check.
+/// - `applied`: [`&[AppliedMigration]`](AppliedMigration): The recorded migrations, see [get_applied_migrations].
+pub fn changed_migrations<'a>(
+    migrations: &'a [Migration],
+    applied: &[AppliedMigration],
+) -> anyhow::Result<Vec<(&'a Migration, String)>> {
+    let mut changed = vec![];
+
+    for migration in migrations {
+        let Some(recorded) = applied
+            .iter()
+            .rev()
+            .find(|x| x.id == migration.id && x.name.as_ref() == Some(&migration.name))
+            .and_then(|x| x.checksum.as_ref())
+        else {
+            continue;
+        };
+
+        let checksum = migration_checksum(migration)?;
+        if checksum != *recorded {
+            changed.push((migration, checksum));
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Applies migrations on the given database with a given driver
+///
+/// See [run_migrate_custom_with_options] for the remaining options.
+pub async fn run_migrate_custom(
+    db_conf: DatabaseConfig,
+    migration_dir: String,
+    log_sql: bool,
+    apply_until: Option<u16>,
+) -> anyhow::Result<()> {
+    run_migrate_custom_with_options(
+        db_conf,
+        MigrateCustomOptions {
+            migration_dir,
+            log_queries: log_sql,
+            apply_until,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Applies migrations on the given database with a given driver
+///
+/// The database is locked while migrating, so concurrent calls apply every migration only once.
+pub async fn run_migrate_custom_with_options(
+    db_conf: DatabaseConfig,
+    options: MigrateCustomOptions,
+) -> anyhow::Result<()> {
+    let MigrateCustomOptions {
+        migration_dir,
+        log_queries: log_sql,
+        apply_until,
+        accept_changed,
+        lock_timeout,
+        atomic,
+    } = options;
+
+    let p = Path::new(migration_dir.as_str());
+    if !p.exists() || p.is_file() {
+        print_message!(
+            "Couldn't find the migration directory in {} \n\n\
+            You can specify an alternative path with --migration-dir <PATH>",
+            migration_dir.as_str()
+        );
+        return Ok(());
+    }
+
+    let existing_migrations = get_existing_migrations(migration_dir.as_str())
+        .with_context(|| "Couldn't retrieve existing migrations")?;
+
+    if existing_migrations.is_empty() {
+        report(
+            Event::NothingToDo {
+                message: "No migrations found.",
+            },
+            "No migrations found.\nExiting.",
+        );
+        return Ok(());
+    }
+
+    let pool = connect_database(&db_conf).await?;
+    let last_migration_table_name = last_migration_table_name(&db_conf);
+
+    acquire_migration_lock(&pool, last_migration_table_name, lock_timeout, log_sql).await?;
+    let result = apply_pending_migrations(
+        &pool,
+        &db_conf,
+        migration_dir.as_str(),
+        existing_migrations,
+        log_sql,
+        apply_until,
+        &accept_changed,
+        atomic,
+    )
+    .await;
+    let released = release_migration_lock(&pool, last_migration_table_name, log_sql).await;
+
+    // A failed release must not hide the error of the migration
+    result.and(released)
+}
+
+/// Applies all pending migrations, must be called while holding the migration lock
+#[allow(clippy::too_many_arguments)]
+async fn apply_pending_migrations(
+    pool: &Database,
+    db_conf: &DatabaseConfig,
+    migration_dir: &str,
+    existing_migrations: Vec<Migration>,
+    log_sql: bool,
+    apply_until: Option<u16>,
+    accept_changed: &[u16],
+    atomic: bool,
+) -> anyhow::Result<()> {
+    let all_migrations = get_all_existing_migrations(migration_dir)
+        .with_context(|| "Couldn't retrieve existing migrations")?;
+
+    create_last_migration_table(pool, db_conf, log_sql).await?;
+    let last_migration_table_name = last_migration_table_name(db_conf);
+    let db_impl = pool.dialect();
+
+    let atomic = atomic
+        && match db_impl {
+            #[cfg(feature = "mysql")]
+            DBImpl::MySQL => {
+                print_warning!(
+                    "MySQL commits implicitly after each DDL statement, \
+                    so migrations can not be applied atomically. \
+                    Each migration is applied in its own transaction instead."
+                );
+                false
+            }
+            #[allow(unreachable_patterns)]
+            _ => true,
+        };
+
+    let applied = get_applied_migrations(pool, last_migration_table_name, log_sql).await?;
+    let applied_ids = applied_migration_ids(&applied, &all_migrations);
+
+    // If an applied migration could not be found in existing migrations,
+    // abort as there's no way to determine what to do next
+    if let Some(unknown) = applied.iter().find(|x| {
+        !all_migrations
+            .iter()
+            .any(|y| y.id == x.id || y.replaces.contains(&x.id))
+    }) {
+        let id = unknown.id;
+        return Err(anyhow!(
+            r#"Applied migration {id} was not found in current migrations.
+
+Can not proceed any further without damaging data.
+To correct, remove the record with --fake-unapply {id} and record the migration
+that matches the state of the database with --fake <MIGRATION_ID>."#,
+        ));
+    }
+
+    // Prefer squashed migrations for migrations that haven't been applied yet
+    let last_applied = existing_migrations
+        .iter()
+        .rev()
+        .find(|x| applied_ids.contains(&x.id))
+        .map(|x| x.id);
+    let existing_migrations = substitute_squashed_migrations(
+        migration_dir,
+        existing_migrations,
+        last_applied,
+        apply_until,
+    )
+    .with_context(|| "Couldn't retrieve squashed migrations")?;
+
+    for (migration, checksum) in changed_migrations(&existing_migrations, &applied)? {
+        if !accept_changed.contains(&migration.id) {
+            return Err(anyhow!(
+                r#"Migration {:04}_{} was changed after it has been applied.
+
+Revert the changes to the migration file and create a new migration instead.
+If the changes match the state of the database, accept them with --accept-changed {}."#,
+                migration.id,
+                migration.name,
+                migration.id
+            ));
+        }
+
+        pool.execute::<Nothing>(
+            log_sql!(
+                format!(
+                    "UPDATE {last_migration_table_name} SET checksum = '{checksum}' WHERE migration_id = {} AND migration_name = '{}';",
+                    migration.id, migration.name
+                ),
+                log_sql
+            ),
+            Vec::new(),
+        )
+        .await
+        .with_context(|| format!("Error while updating the checksum of migration {}", migration.id))?;
+
+        print_message!(
+            "Accepted changes of applied migration {:04}_{}",
+            migration.id,
+            migration.name
+        );
+    }
+
+    // Migrations must be applied in order, so no applied migration may follow a pending one
+    let last_applied_idx = existing_migrations
+        .iter()
+        .rposition(|x| applied_ids.contains(&x.id));
+    if let Some(last_applied_idx) = last_applied_idx {
+        if let Some(pending) = existing_migrations[..last_applied_idx]
+            .iter()
+            .find(|x| !applied_ids.contains(&x.id))
+        {
+            return Err(anyhow!(
+                r#"Migration {:04}_{} has not been applied, but later migrations have been.
+
+Apply the migration manually and record it with --fake {}."#,
+                pending.id,
+                pending.name,
+                pending.id
+            ));
+        }
+    }
+
+    let apply_until_idx = match apply_until {
+        None => None,
+        Some(apply_until) => Some(
+            existing_migrations
+                .iter()
+                .position(|x| x.id == apply_until)
+                .ok_or_else(|| anyhow!("Migration {apply_until:04} was not found."))?,
+        ),
+    };
+
+    let pending: Vec<&Migration> = existing_migrations
+        .iter()
+        .enumerate()
+        .take_while(|(idx, _)| !apply_until_idx.is_some_and(|x| *idx > x))
+        .map(|(_, migration)| migration)
+        .filter(|migration| !applied_ids.contains(&migration.id))
+        .collect();
+    let applied_any = !pending.is_empty();
+
+    // State of the models before the first pending migration, used to rebuild tables on SQLite.
+    // It is unknown if a RawSQL operation without the StructureSafe flag was applied.
+    let first_pending = existing_migrations
+        .iter()
+        .position(|x| !applied_ids.contains(&x.id))
+        .unwrap_or(existing_migrations.len());
+    let mut models = convert_migrations_to_internal_models(&existing_migrations[..first_pending])
+        .ok()
+        .map(|x| x.models);
+
+    if atomic {
+        for migration in &pending {
+            if !is_atomic(migration_dir, migration)? {
+                return Err(anyhow!(
+                    "Migration {:04}_{} is not atomic and can not be applied with --atomic",
+                    migration.id,
+                    migration.name
+                ));
+            }
+        }
+    }
+
+    if atomic && applied_any {
+        apply_migrations_atomic(
+            db_impl,
+            &pending,
+            models.as_deref(),
+            pool,
+            last_migration_table_name,
+            log_sql,
+        )
+        .await?;
+    } else {
+        for migration in pending {
+            let checksum = migration_checksum(migration)?;
+
+            // Migrations that failed before are resumed after the last applied operation
+            let applied_operations = applied
+                .iter()
+                .rev()
+                .find(|x| x.id == migration.id)
+                .and_then(|x| x.applied_operations);
+
+            if applied_operations.is_some() || !is_atomic(migration_dir, migration)? {
+                apply_migration_non_atomic(
+                    db_impl,
+                    migration,
+                    &checksum,
+                    applied_operations,
+                    models.as_deref(),
+                    pool,
+                    last_migration_table_name,
+                    log_sql,
+                )
+                .await?;
+            } else {
                 apply_migration(
                     db_impl,
                     migration,
-                    &pool,
+                    &checksum,
+                    models.as_deref(),
+                    pool,
                     last_migration_table_name,
                     log_sql,
                 )
                 .await?;
-
-                if let Some(apply_until) = apply_until {
-                    if migration.id == apply_until {
-                        println!(
-                            "Applied all migrations until (inclusive) migration {apply_until:04}"
-                        );
-                        break;
-                    }
-                }
             }
+
+            apply_migration_to_state(&mut models, migration);
         }
-        Some(id) => {
-            let id = id as u16;
-            // Search for last applied migration
-            if existing_migrations.iter().any(|x| x.id == id) {
-                let mut apply = false;
-                for (idx, migration) in existing_migrations.iter().enumerate() {
-                    if apply {
-                        apply_migration(
-                            db_impl,
-                            migration,
-                            &pool,
-                            last_migration_table_name,
-                            log_sql,
-                        )
-                        .await?;
-                        continue;
-                    }
-
-                    if migration.id == id {
-                        apply = true;
-
-                        if idx == existing_migrations.len() - 1 {
-                            println!("All migration have already been applied.");
-                        }
-                    }
-
-                    if let Some(apply_until) = apply_until {
-                        match migration.id.cmp(&apply_until) {
-                            Ordering::Equal => {
-                                if apply {
-                                    println!(
-                                        "Applied all migrations until (inclusive) migration {apply_until:04}"
-                                    );
-                                } else {
-                                    println!(
-                                        "All migrations until (inclusive) migration {apply_until:04} have already been applied"
-                                    );
-                                }
-                                break;
-                            }
-                            Ordering::Greater => break,
-                            Ordering::Less => {}
-                        }
-                    }
-                }
-            } else {
-                // If last applied migration could not be found in existing migrations,
-                // panic as there's no way to determine what to do next
-                return Err(anyhow!(
-                    r#"Last applied migration {id} was not found in current migrations.
- 
-Can not proceed any further without damaging data.
-To correct, empty the {last_migration_table_name} table or reset the whole database."#,
-                ));
-            }
+    }
+
+    match (apply_until, applied_any) {
+        (Some(apply_until), true) => {
+            print_message!("Applied all migrations until (inclusive) migration {apply_until:04}")
+        }
+        (Some(apply_until), false) => {
+            let message = format!(
+                "All migrations until (inclusive) migration {apply_until:04} have already been applied"
+            );
+            report(Event::NothingToDo { message: &message }, &message);
+        }
+        (None, true) => {}
+        (None, false) => {
+            let message = "All migration have already been applied.";
+            report(Event::NothingToDo { message }, message);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the SQL statements of the migrations instead of applying them
+///
+/// No connection to the database is made, so it is assumed that all migrations
+/// before `from` have been applied. If `from` is not set, the output starts
+/// from an empty database.
+pub fn run_migrate_dry_run(
+    db_conf: &DatabaseConfig,
+    migration_dir: String,
+    from: Option<u16>,
+    apply_until: Option<u16>,
+) -> anyhow::Result<()> {
+    let p = Path::new(migration_dir.as_str());
+    if !p.exists() || p.is_file() {
+        print_message!(
+            "Couldn't find the migration directory in {} \n\n\
+            You can specify an alternative path with --migration-dir <PATH>",
+            migration_dir.as_str()
+        );
+        return Ok(());
+    }
+
+    let existing_migrations = get_existing_migrations(migration_dir.as_str())
+        .with_context(|| "Couldn't retrieve existing migrations")?;
+
+    let last_applied = match from {
+        None => None,
+        Some(from) => {
+            let idx = existing_migrations
+                .iter()
+                .position(|x| x.id == from)
+                .ok_or_else(|| anyhow!("Migration {from:04} was not found."))?;
+            idx.checked_sub(1).map(|x| existing_migrations[x].id)
+        }
+    };
+    let existing_migrations = substitute_squashed_migrations(
+        migration_dir.as_str(),
+        existing_migrations,
+        last_applied,
+        apply_until,
+    )
+    .with_context(|| "Couldn't retrieve squashed migrations")?;
+
+    // A squashed migration has the id of the last migration it replaces
+    let first = from.map_or(0, |from| {
+        existing_migrations
+            .iter()
+            .position(|x| x.id >= from)
+            .unwrap_or(existing_migrations.len())
+    });
+
+    let db_impl = dialect_of_driver(&db_conf.driver);
+    let mut models = convert_migrations_to_internal_models(&existing_migrations[..first])
+        .ok()
+        .map(|x| x.models);
+    for migration in &existing_migrations[first..] {
+        print_migration_sql(db_impl, migration, models.as_deref())?;
+        apply_migration_to_state(&mut models, migration);
+
+        if apply_until == Some(migration.id) {
+            break;
         }
     }
 
@@ -271,8 +1250,8 @@ To correct, empty the {last_migration_table_name} table or reset the whole datab
 pub async fn run_migrate(options: MigrateOptions) -> anyhow::Result<()> {
     let db_conf_path = Path::new(options.database_config.as_str());
 
-    if !&db_conf_path.exists() {
-        println!(
+    if database_url().is_none() && !&db_conf_path.exists() {
+        print_message!(
             "Couldn't find the database configuration file, created {} and exiting",
             options.database_config.as_str()
         );
@@ -282,11 +1261,49 @@ pub async fn run_migrate(options: MigrateOptions) -> anyhow::Result<()> {
 
     let db_conf = deserialize_db_conf(db_conf_path)?;
 
-    run_migrate_custom(
+    if options.dry_run {
+        return run_migrate_dry_run(
+            &db_conf,
+            options.migration_dir,
+            options.dry_run_from,
+            options.apply_until,
+        );
+    }
+
+    if let Some(migration_id) = options.fake.or(options.fake_unapply) {
+        return run_fake_custom(
+            db_conf,
+            options.migration_dir,
+            options.log_queries,
+            migration_id,
+            options.fake_unapply.is_some(),
+            options.lock_timeout,
+        )
+        .await;
+    }
+
+    if let Some(rollback_to) = options.rollback_to {
+        return run_rollback_custom(
+            db_conf,
+            options.migration_dir,
+            options.log_queries,
+            rollback_to,
+            &options.accept_changed,
+            options.lock_timeout,
+        )
+        .await;
+    }
+
+    run_migrate_custom_with_options(
         db_conf,
-        options.migration_dir,
-        options.log_queries,
-        options.apply_until,
+        MigrateCustomOptions {
+            migration_dir: options.migration_dir,
+            log_queries: options.log_queries,
+            apply_until: options.apply_until,
+            accept_changed: options.accept_changed,
+            lock_timeout: options.lock_timeout,
+            atomic: options.atomic,
+        },
     )
     .await
 }
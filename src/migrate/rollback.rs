@@ -0,0 +1,510 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use rorm_db::executor::{Executor, Nothing};
+use rorm_db::Database;
+use rorm_declaration::config::DatabaseConfig;
+use rorm_declaration::imr::Model;
+use rorm_declaration::migration::{Migration, Operation};
+use rorm_sql::DBImpl;
+
+use crate::migrate::lock::{acquire_migration_lock, release_migration_lock};
+use crate::migrate::sql_builder::{
+    check_non_atomic_migration, check_sqlite_foreign_keys, migration_to_sql,
+    migration_to_statements, rebuilds_sqlite_table, with_sqlite_foreign_keys_disabled,
+};
+use crate::migrate::{
+    applied_migration_ids, changed_migrations, connect_database, create_last_migration_table,
+    get_applied_migrations, last_migration_table_name,
+};
+use crate::utils::migrations::{
+    apply_operation, convert_migrations_to_internal_models, get_all_existing_migrations,
+    get_existing_migrations, is_atomic,
+};
+use crate::utils::output::{print_sql, report, Event};
+use crate::{log_sql, print_message};
+
+/// Helper method to create the operation that reverts the given operation.
+///
+/// - `models`: [`&[Model]`](Model): The models before the operation was applied.
+/// - `operation`: [`&Operation`](Operation): The operation to revert.
+fn invert_operation(models: &[Model], operation: &Operation) -> anyhow::Result<Operation> {
+    Ok(match operation {
+        Operation::CreateModel { name, .. } => Operation::DeleteModel { name: name.clone() },
+        Operation::RenameModel { old, new } => Operation::RenameModel {
+            old: new.clone(),
+            new: old.clone(),
+        },
+        Operation::DeleteModel { name } => {
+            let model = models
+                .iter()
+                .find(|x| x.name == *name)
+                .ok_or_else(|| anyhow!("Can not restore model {name} as its fields are unknown"))?;
+
+            Operation::CreateModel {
+                name: name.clone(),
+                fields: model.fields.clone(),
+            }
+        }
+        Operation::CreateField { model, field } => Operation::DeleteField {
+            model: model.clone(),
+            name: field.name.clone(),
+        },
+        Operation::RenameField {
+            table_name,
+            old,
+            new,
+        } => Operation::RenameField {
+            table_name: table_name.clone(),
+            old: new.clone(),
+            new: old.clone(),
+        },
+        Operation::DeleteField { model, name } => {
+            let field = models
+                .iter()
+                .find(|x| x.name == *model)
+                .and_then(|x| x.fields.iter().find(|y| y.name == *name))
+                .ok_or_else(|| {
+                    anyhow!("Can not restore field {name} of model {model} as it is unknown")
+                })?;
+
+            Operation::CreateField {
+                model: model.clone(),
+                field: field.clone(),
+            }
+        }
+        Operation::RawSQL { .. } => return Err(anyhow!("RawSQL operations can not be reverted")),
+    })
+}
+
+/// Helper method to create a migration that reverts the given migration.
+///
+/// The state of the models before the migration is determined by replaying
+/// all previous migrations, so deleted models and fields can be restored.
+///
+/// - `previous`: [`&[Migration]`](Migration): All migrations applied before `migration`.
+/// - `migration`: [`&Migration`](Migration): The migration to revert.
+pub fn invert_migration(
+    previous: &[Migration],
+    migration: &Migration,
+) -> anyhow::Result<Migration> {
+    let mut models = convert_migrations_to_internal_models(previous)
+        .with_context(|| "Couldn't determine the state of the models before the migration")?
+        .models;
+
+    let mut operations = vec![];
+    for operation in &migration.operations {
+        operations.push(invert_operation(&models, operation).with_context(|| {
+            format!(
+                "Migration {:04}_{} can not be reverted",
+                migration.id, migration.name
+            )
+        })?);
+        apply_operation(&mut models, operation)?;
+    }
+    operations.reverse();
+
+    Ok(Migration {
+        operations,
+        ..migration.clone()
+    })
+}
+
+/// Helper method to revert one migration. Removes it also from the last migration table.
+///
+/// Migrations that opt out of transactions with `Atomic = false` are reverted
+/// statement by statement, a failure leaves the migration partially reverted.
+///
+/// - `migration`: [`&Migration`](Migration): The reverting migration, see [invert_migration].
+/// - `models`: [`Option<&[Model]>`](Model): The models before reverting the migration, if known.
+/// - `atomic`: [`bool`]: Revert the migration in a transaction, see [is_atomic].
+/// - `db`: [`&Database`](Database): Database to revert the migration on.
+/// - `last_migration_table_name`: [`&str`]: Name of the table successful applied migrations are inserted into.
+pub async fn revert_migration(
+    dialect: DBImpl,
+    migration: &Migration,
+    models: Option<&[Model]>,
+    atomic: bool,
+    db: &Database,
+    last_migration_table_name: &str,
+    do_log: bool,
+) -> anyhow::Result<()> {
+    let delete = format!(
+        "DELETE FROM {last_migration_table_name} WHERE migration_id = {};",
+        migration.id
+    );
+    let delete_err = || {
+        format!(
+            "Error while removing reverted migration {} from last migration table",
+            migration.id
+        )
+    };
+
+    if atomic {
+        let rebuild = rebuilds_sqlite_table(dialect, migration, models);
+
+        with_sqlite_foreign_keys_disabled(db, rebuild, do_log, async {
+            let mut tx = db
+                .start_transaction()
+                .await
+                .with_context(|| format!("Error while starting transaction {}", migration.id))?;
+
+            let mut result = migration_to_sql(&mut tx, dialect, migration, models, do_log).await;
+            if result.is_ok() && rebuild {
+                result = check_sqlite_foreign_keys(&mut tx, do_log).await;
+            }
+            if let Err(e) = result {
+                tx.rollback()
+                    .await
+                    .with_context(|| "Error while rollback in transaction")?;
+                return Err(e);
+            }
+
+            tx.execute::<Nothing>(log_sql!(delete, do_log), Vec::new())
+                .await
+                .with_context(delete_err)?;
+
+            tx.commit()
+                .await
+                .with_context(|| format!("Error while committing transaction {}", migration.id))
+        })
+        .await?;
+    } else {
+        check_non_atomic_migration(dialect, migration, models)?;
+
+        for (query_string, bind_params) in migration_to_statements(dialect, migration, models)? {
+            if do_log {
+                print_sql(&query_string);
+            }
+
+            db.execute::<Nothing>(query_string, bind_params)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Reverting migration {:04}_{} failed, it has been reverted partially \
+                        and has to be fixed manually.",
+                        migration.id, migration.name
+                    )
+                })?;
+        }
+
+        db.execute::<Nothing>(log_sql!(delete, do_log), Vec::new())
+            .await
+            .with_context(delete_err)?;
+    }
+
+    report(
+        Event::MigrationReverted {
+            id: migration.id,
+            name: migration.name.as_str(),
+        },
+        format!("Reverted migration {:04}_{}", migration.id, migration.name),
+    );
+
+    Ok(())
+}
+
+/// Reverts all migrations applied after the given migration on the given database
+///
+/// Applied migrations are reverted in reverse order, each one in its own transaction
+/// unless it sets `Atomic = false`.
+/// If `rollback_to` is `0`, all migrations are reverted.
+pub async fn run_rollback_custom(
+    db_conf: DatabaseConfig,
+    migration_dir: String,
+    log_sql: bool,
+    rollback_to: u16,
+    accept_changed: &[u16],
+    lock_timeout: Duration,
+) -> anyhow::Result<()> {
+    let p = Path::new(migration_dir.as_str());
+    if !p.exists() || p.is_file() {
+        print_message!(
+            "Couldn't find the migration directory in {} \n\n\
+            You can specify an alternative path with --migration-dir <PATH>",
+            migration_dir.as_str()
+        );
+        return Ok(());
+    }
+
+    let existing_migrations = get_existing_migrations(migration_dir.as_str())
+        .with_context(|| "Couldn't retrieve existing migrations")?;
+
+    let target = if rollback_to == 0 {
+        None
+    } else {
+        Some(
+            existing_migrations
+                .iter()
+                .position(|x| x.id == rollback_to)
+                .ok_or_else(|| anyhow!("Migration {rollback_to:04} was not found."))?,
+        )
+    };
+
+    let pool = connect_database(&db_conf).await?;
+    let last_migration_table_name = last_migration_table_name(&db_conf);
+
+    acquire_migration_lock(&pool, last_migration_table_name, lock_timeout, log_sql).await?;
+    let result = revert_applied_migrations(
+        &pool,
+        &db_conf,
+        migration_dir.as_str(),
+        existing_migrations,
+        target,
+        rollback_to,
+        accept_changed,
+        log_sql,
+    )
+    .await;
+    let released = release_migration_lock(&pool, last_migration_table_name, log_sql).await;
+
+    // A failed release must not hide the error of the migration
+    result.and(released)
+}
+
+/// Reverts the applied migrations after `target`, must be called while holding the migration lock
+async fn revert_applied_migrations(
+    pool: &Database,
+    db_conf: &DatabaseConfig,
+    migration_dir: &str,
+    existing_migrations: Vec<Migration>,
+    target: Option<usize>,
+    rollback_to: u16,
+    accept_changed: &[u16],
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    create_last_migration_table(pool, db_conf, log_sql).await?;
+    let last_migration_table_name = last_migration_table_name(db_conf);
+    let db_impl = pool.dialect();
+
+    let applied = get_applied_migrations(pool, last_migration_table_name, log_sql).await?;
+    let applied_ids = applied_migration_ids(&applied, &get_all_existing_migrations(migration_dir)?);
+
+    // Non-atomic migrations that have not been completed yet are reverted partially
+    let applied_operations = |migration: &Migration| {
+        applied
+            .iter()
+            .filter(|x| x.id == migration.id)
+            .filter(|x| x.name.is_none() || x.name.as_ref() == Some(&migration.name))
+            .find_map(|x| x.applied_operations)
+    };
+    let is_applied = |migration: &Migration| {
+        applied_ids.contains(&migration.id) || applied_operations(migration).is_some()
+    };
+
+    let Some(last) = existing_migrations.iter().rposition(is_applied) else {
+        let message = "No migrations have been applied yet.";
+        report(Event::NothingToDo { message }, message);
+        return Ok(());
+    };
+
+    let first = match target {
+        Some(target) if target >= last => {
+            let message = format!("Migration {rollback_to:04} is the last applied migration or has not been applied yet.");
+            report(Event::NothingToDo { message: &message }, &message);
+            return Ok(());
+        }
+        Some(target) => target + 1,
+        None => 0,
+    };
+
+    // The inverse of a changed migration doesn't match the database
+    for (migration, _) in changed_migrations(&existing_migrations[first..=last], &applied)? {
+        if !accept_changed.contains(&migration.id) {
+            return Err(anyhow!(
+                r#"Migration {:04}_{} was changed after it has been applied, it can't be reverted safely.
+
+Revert the changes to the migration file before rolling back.
+If the changes match the state of the database, accept them with --accept-changed {}."#,
+                migration.id,
+                migration.name,
+                migration.id
+            ));
+        }
+    }
+
+    // Check that all migrations can be reverted before touching the database
+    let reverting = (first..=last)
+        .filter(|idx| is_applied(&existing_migrations[*idx]))
+        .map(|idx| {
+            let mut migration = existing_migrations[idx].clone();
+            if !applied_ids.contains(&migration.id) {
+                if let Some(applied_operations) = applied_operations(&migration) {
+                    migration.operations.truncate(applied_operations as usize);
+                }
+            }
+
+            let mut chain = existing_migrations[..idx].to_vec();
+            chain.push(migration.clone());
+            let models = convert_migrations_to_internal_models(&chain)
+                .ok()
+                .map(|x| x.models);
+            let atomic = is_atomic(migration_dir, &migration)?;
+            invert_migration(&existing_migrations[..idx], &migration)
+                .map(|migration| (migration, models, atomic))
+        })
+        .collect::<anyhow::Result<Vec<(Migration, Option<Vec<Model>>, bool)>>>()?;
+
+    for (migration, models, atomic) in reverting.iter().rev() {
+        revert_migration(
+            db_impl,
+            migration,
+            models.as_deref(),
+            *atomic,
+            pool,
+            last_migration_table_name,
+            log_sql,
+        )
+        .await?;
+    }
+
+    if rollback_to == 0 {
+        print_message!("Reverted all migrations");
+    } else {
+        print_message!("Reverted all migrations after migration {rollback_to:04}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rorm_declaration::imr::{Annotation, DbType, Field, Model};
+    use rorm_declaration::migration::{Migration, Operation};
+
+    use crate::migrate::rollback::{invert_migration, invert_operation};
+
+    fn field(name: &str) -> Field {
+        Field {
+            name: name.to_string(),
+            db_type: DbType::Int64,
+            annotations: vec![Annotation::NotNull],
+            source_defined_at: None,
+        }
+    }
+
+    fn migration(id: u16, operations: Vec<Operation>) -> Migration {
+        Migration {
+            hash: "".to_string(),
+            initial: id == 1,
+            id,
+            name: format!("migration_{id}"),
+            dependency: (id > 1).then_some(id - 1),
+            replaces: vec![],
+            operations,
+        }
+    }
+
+    #[test]
+    fn test_invert_operation() {
+        let models = vec![Model {
+            name: "foo".to_string(),
+            fields: vec![field("id"), field("bar")],
+            source_defined_at: None,
+        }];
+
+        assert!(matches!(
+            invert_operation(
+                &models,
+                &Operation::CreateModel {
+                    name: "baz".to_string(),
+                    fields: vec![field("id")],
+                }
+            ),
+            Ok(Operation::DeleteModel { name }) if name == "baz"
+        ));
+        assert!(matches!(
+            invert_operation(
+                &models,
+                &Operation::DeleteModel {
+                    name: "foo".to_string()
+                }
+            ),
+            Ok(Operation::CreateModel { name, fields })
+                if name == "foo" && fields == vec![field("id"), field("bar")]
+        ));
+        assert!(matches!(
+            invert_operation(
+                &models,
+                &Operation::RenameModel {
+                    old: "foo".to_string(),
+                    new: "baz".to_string(),
+                }
+            ),
+            Ok(Operation::RenameModel { old, new }) if old == "baz" && new == "foo"
+        ));
+        assert!(matches!(
+            invert_operation(
+                &models,
+                &Operation::RenameField {
+                    table_name: "foo".to_string(),
+                    old: "bar".to_string(),
+                    new: "baz".to_string(),
+                }
+            ),
+            Ok(Operation::RenameField { table_name, old, new })
+                if table_name == "foo" && old == "baz" && new == "bar"
+        ));
+        assert!(invert_operation(
+            &models,
+            &Operation::DeleteField {
+                model: "foo".to_string(),
+                name: "unknown".to_string(),
+            }
+        )
+        .is_err());
+        assert!(invert_operation(
+            &models,
+            &Operation::RawSQL {
+                structure_safe: true,
+                mysql: "SELECT 1;".to_string(),
+                postgres: "SELECT 1;".to_string(),
+                sqlite: "SELECT 1;".to_string(),
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_invert_migration() {
+        let previous = vec![migration(
+            1,
+            vec![Operation::CreateModel {
+                name: "foo".to_string(),
+                fields: vec![field("id"), field("bar")],
+            }],
+        )];
+        let migration = migration(
+            2,
+            vec![
+                Operation::DeleteField {
+                    model: "foo".to_string(),
+                    name: "bar".to_string(),
+                },
+                Operation::CreateField {
+                    model: "foo".to_string(),
+                    field: field("baz"),
+                },
+            ],
+        );
+
+        let reverting = invert_migration(&previous, &migration).unwrap();
+        assert_eq!(reverting.id, 2);
+        // The operations are reverted in reverse order, the deleted field is restored
+        // from the state replayed from the previous migrations
+        assert_eq!(reverting.operations.len(), 2);
+        assert!(matches!(
+            &reverting.operations[0],
+            Operation::DeleteField { model, name } if model == "foo" && name == "baz"
+        ));
+        assert!(matches!(
+            &reverting.operations[1],
+            Operation::CreateField { model, field: restored }
+                if model == "foo" && *restored == field("bar")
+        ));
+
+        // The field is unknown without the previous migrations
+        assert!(invert_migration(&[], &migration).is_err());
+    }
+}
@@ -1,5 +1,11 @@
-use rorm_db::executor::{Executor, Nothing};
+use std::future::Future;
+
+use anyhow::{anyhow, Context};
+use rorm_db::executor::{All, Executor, Nothing};
 use rorm_db::transaction::Transaction;
+use rorm_db::Database;
+use rorm_declaration::config::DatabaseDriver;
+use rorm_declaration::imr::{Annotation, Field, Model};
 use rorm_declaration::migration::{Migration, Operation};
 use rorm_sql::alter_table::{AlterTable, AlterTableOperation};
 use rorm_sql::create_table::CreateTable;
@@ -7,61 +13,127 @@ use rorm_sql::drop_table::DropTable;
 use rorm_sql::value::Value;
 use rorm_sql::DBImpl;
 
-/// Helper method to convert a migration to a transaction string
+use crate::log_sql;
+use crate::utils::migrations::{apply_operation, apply_operation_to_state};
+use crate::utils::output::{print_sql, report, Event};
+
+/// Helper method to retrieve the database implementation of a driver without connecting to it
+///
+/// - `driver`: [`&DatabaseDriver`](DatabaseDriver): The configured driver.
+pub fn dialect_of_driver(driver: &DatabaseDriver) -> DBImpl {
+    match driver {
+        #[cfg(feature = "sqlite")]
+        DatabaseDriver::SQLite { .. } => DBImpl::SQLite,
+        #[cfg(feature = "mysql")]
+        DatabaseDriver::MySQL { .. } => DBImpl::MySQL,
+        #[cfg(feature = "postgres")]
+        DatabaseDriver::Postgres { .. } => DBImpl::Postgres,
+    }
+}
+
+/// Helper method to retrieve the database implementation by its name
+///
+/// - `name`: [`&str`]: One of `sqlite`, `mysql` or `postgres`.
+pub fn dialect_by_name(name: &str) -> anyhow::Result<DBImpl> {
+    match name {
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Ok(DBImpl::SQLite),
+        #[cfg(feature = "mysql")]
+        "mysql" => Ok(DBImpl::MySQL),
+        #[cfg(feature = "postgres")]
+        "postgres" => Ok(DBImpl::Postgres),
+        _ => Err(anyhow!("The dialect {name} is not supported by this build")),
+    }
+}
+
+/// Helper method to convert a migration to a list of statements
+///
+/// No database connection is required to build the statements.
 ///
 /// - `db_impl`: [`DBImpl`]: The database implementation to use.
 /// - `migration`: [`&Migration`](Migration): Reference to the migration that should be converted.
-pub async fn migration_to_sql<'a>(
-    tx: &'a mut Transaction,
+/// - `models`: [`Option<&[Model]>`](Model): The models before the migration, if known.
+pub fn migration_to_statements<'a>(
     db_impl: DBImpl,
     migration: &'a Migration,
-    do_log: bool,
-) -> anyhow::Result<()> {
+    models: Option<&[Model]>,
+) -> anyhow::Result<Vec<(String, Vec<Value<'a>>)>> {
+    let mut statements = vec![];
+    let mut models = models.map(<[Model]>::to_vec);
+
     for operation in &migration.operations {
-        match &operation {
-            Operation::CreateModel { name, fields } => {
-                let mut create_table = db_impl.create_table(name.as_str());
-
-                for field in fields {
-                    create_table = create_table.add_column(db_impl.create_column(
-                        name.as_str(),
-                        field.name.as_str(),
-                        field.db_type,
-                        &field.annotations,
-                    ));
-                }
+        statements.extend(operation_to_statements(
+            db_impl,
+            operation,
+            models.as_deref(),
+        )?);
+        apply_operation_to_state(&mut models, operation);
+    }
 
-                let statements = create_table.build()?;
+    Ok(statements)
+}
 
-                for (query_string, query_bind_params) in statements {
-                    execute_statement(tx, query_string, query_bind_params, do_log).await?;
+/// Helper method to convert a single operation to a list of statements
+///
+/// - `db_impl`: [`DBImpl`]: The database implementation to use.
+/// - `operation`: [`&Operation`](Operation): Reference to the operation that should be converted.
+/// - `models`: [`Option<&[Model]>`](Model): The models before the operation, if known.
+///
+/// If the models are known, operations SQLite can't apply with `ALTER TABLE`
+/// are converted to a rebuild of the table, see [rebuild_sqlite_table].
+pub fn operation_to_statements<'a>(
+    db_impl: DBImpl,
+    operation: &'a Operation,
+    models: Option<&[Model]>,
+) -> anyhow::Result<Vec<(String, Vec<Value<'a>>)>> {
+    if let Some(models) = models {
+        if let Some(model) = sqlite_rebuild_target(db_impl, models, operation) {
+            if let Some(before) = models.iter().find(|x| x.name == model) {
+                let mut after = models.to_vec();
+                apply_operation(&mut after, operation)?;
+
+                if let Some(after) = after.iter().find(|x| x.name == model) {
+                    return rebuild_sqlite_table(db_impl, before, after);
                 }
             }
-            Operation::RenameModel { old, new } => {
-                let statements = db_impl
+        }
+    }
+
+    let mut statements = vec![];
+
+    match operation {
+        Operation::CreateModel { name, fields } => {
+            let mut create_table = db_impl.create_table(name.as_str());
+
+            for field in fields {
+                create_table = create_table.add_column(db_impl.create_column(
+                    name.as_str(),
+                    field.name.as_str(),
+                    field.db_type,
+                    &field.annotations,
+                ));
+            }
+
+            statements.extend(create_table.build()?);
+        }
+        Operation::RenameModel { old, new } => {
+            statements.extend(
+                db_impl
                     .alter_table(
                         old.as_str(),
                         AlterTableOperation::RenameTo {
                             name: new.to_string(),
                         },
                     )
-                    .build()?;
-
-                for (query_string, query_bind_params) in statements {
-                    execute_statement(tx, query_string, query_bind_params, do_log).await?;
-                }
-            }
-            Operation::DeleteModel { name } => {
-                let query_string = db_impl.drop_table(name.as_str()).build();
-
-                if do_log {
-                    println!("{}", query_string.as_str());
-                }
-
-                tx.execute::<Nothing>(query_string, Vec::new()).await?;
-            }
-            Operation::CreateField { model, field } => {
-                let statements = db_impl
+                    .build()?,
+            );
+        }
+        Operation::DeleteModel { name } => {
+            statements.push((db_impl.drop_table(name.as_str()).build(), Vec::new()));
+        }
+        Operation::CreateField { model, field } => {
+            statements.extend(
+                db_impl
                     .alter_table(
                         model.as_str(),
                         AlterTableOperation::AddColumn {
@@ -73,18 +145,16 @@ pub async fn migration_to_sql<'a>(
                             ),
                         },
                     )
-                    .build()?;
-
-                for (query_string, query_bind_params) in statements {
-                    execute_statement(tx, query_string, query_bind_params, do_log).await?;
-                }
-            }
-            Operation::RenameField {
-                table_name,
-                old,
-                new,
-            } => {
-                let statements = db_impl
+                    .build()?,
+            );
+        }
+        Operation::RenameField {
+            table_name,
+            old,
+            new,
+        } => {
+            statements.extend(
+                db_impl
                     .alter_table(
                         table_name.as_str(),
                         AlterTableOperation::RenameColumnTo {
@@ -92,47 +162,395 @@ pub async fn migration_to_sql<'a>(
                             new_column_name: new.to_string(),
                         },
                     )
-                    .build()?;
-
-                for (query_string, query_bind_params) in statements {
-                    execute_statement(tx, query_string, query_bind_params, do_log).await?;
-                }
-            }
-            Operation::DeleteField { model, name } => {
-                let statements = db_impl
+                    .build()?,
+            );
+        }
+        Operation::DeleteField { model, name } => {
+            statements.extend(
+                db_impl
                     .alter_table(
                         model.as_str(),
                         AlterTableOperation::DropColumn { name: name.clone() },
                     )
-                    .build()?;
+                    .build()?,
+            );
+        }
+        #[allow(unused_variables)]
+        Operation::RawSQL {
+            mysql,
+            postgres,
+            sqlite,
+            ..
+        } => match db_impl {
+            #[cfg(feature = "sqlite")]
+            DBImpl::SQLite => {
+                statements.push((sqlite.clone(), Vec::new()));
+            }
+            #[cfg(feature = "postgres")]
+            DBImpl::Postgres => {
+                statements.push((postgres.clone(), Vec::new()));
+            }
+            #[cfg(feature = "mysql")]
+            DBImpl::MySQL => {
+                statements.push((mysql.clone(), Vec::new()));
+            }
+        },
+    }
 
-                for (query_string, query_bind_params) in statements {
-                    execute_statement(tx, query_string, query_bind_params, do_log).await?;
-                }
+    Ok(statements)
+}
+
+/// Helper method to check that a migration can be applied without a transaction
+///
+/// Rebuilding a SQLite table takes several statements, which would leave a half
+/// rebuilt table behind if one of them fails. Such migrations are rejected.
+///
+/// - `db_impl`: [`DBImpl`]: The database implementation to use.
+/// - `migration`: [`&Migration`](Migration): Reference to the migration that should be checked.
+/// - `models`: [`Option<&[Model]>`](Model): The models before the migration, if known.
+pub fn check_non_atomic_migration(
+    db_impl: DBImpl,
+    migration: &Migration,
+    models: Option<&[Model]>,
+) -> anyhow::Result<()> {
+    let mut models = models.map(<[Model]>::to_vec);
+
+    for operation in &migration.operations {
+        if let Some(model) = models
+            .as_deref()
+            .and_then(|x| sqlite_rebuild_target(db_impl, x, operation))
+        {
+            return Err(anyhow!(
+                "Migration {:04}_{} sets Atomic = false, but SQLite has to rebuild table {model} \
+                to apply it, which is only safe in a transaction. Remove Atomic = false or split \
+                the migration.",
+                migration.id,
+                migration.name
+            ));
+        }
+        apply_operation_to_state(&mut models, operation);
+    }
+
+    Ok(())
+}
+
+/// Helper method to check whether SQLite needs to rebuild a table to apply a migration
+///
+/// - `db_impl`: [`DBImpl`]: The database implementation to use.
+/// - `migration`: [`&Migration`](Migration): Reference to the migration that should be checked.
+/// - `models`: [`Option<&[Model]>`](Model): The models before the migration, if known.
+pub fn rebuilds_sqlite_table(
+    db_impl: DBImpl,
+    migration: &Migration,
+    models: Option<&[Model]>,
+) -> bool {
+    let Some(models) = models else {
+        return false;
+    };
+    let mut models = models.to_vec();
+
+    for operation in &migration.operations {
+        if sqlite_rebuild_target(db_impl, &models, operation).is_some() {
+            return true;
+        }
+        if apply_operation(&mut models, operation).is_err() {
+            return false;
+        }
+    }
+
+    false
+}
+
+/// Helper method to run `f` with foreign keys disabled on SQLite, if `disable` is set.
+///
+/// SQLite ignores `PRAGMA foreign_keys` within a transaction, so `f` has to start
+/// its own transaction and should check the foreign keys before committing it,
+/// see [check_sqlite_foreign_keys]. The connection must not be shared, as the
+/// setting applies to the whole connection.
+///
+/// - `db`: [`&Database`](Database): Database to run `f` on.
+/// - `disable`: [`bool`]: Whether foreign keys should be disabled.
+/// - `do_log`: [`bool`]: Log all SQL statements.
+/// - `f`: [`Future`]: The future to run.
+pub async fn with_sqlite_foreign_keys_disabled<T>(
+    db: &Database,
+    disable: bool,
+    do_log: bool,
+    f: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    if !disable {
+        return f.await;
+    }
+
+    db.execute::<Nothing>(
+        log_sql!("PRAGMA foreign_keys = OFF;".to_string(), do_log),
+        Vec::new(),
+    )
+    .await
+    .with_context(|| "Error while disabling foreign keys")?;
+
+    let result = f.await;
+
+    db.execute::<Nothing>(
+        log_sql!("PRAGMA foreign_keys = ON;".to_string(), do_log),
+        Vec::new(),
+    )
+    .await
+    .with_context(|| "Error while enabling foreign keys")?;
+
+    result
+}
+
+/// Helper method to fail if any row violates a foreign key on SQLite
+///
+/// Must be called before committing a transaction that ran with foreign keys disabled,
+/// see [with_sqlite_foreign_keys_disabled].
+///
+/// - `tx`: [`&mut Transaction`](Transaction): Transaction to check.
+/// - `do_log`: [`bool`]: Log all SQL statements.
+pub async fn check_sqlite_foreign_keys(tx: &mut Transaction, do_log: bool) -> anyhow::Result<()> {
+    let rows = tx
+        .execute::<All>(
+            log_sql!("PRAGMA foreign_key_check;".to_string(), do_log),
+            Vec::new(),
+        )
+        .await
+        .with_context(|| "Error while checking foreign keys")?;
+
+    let mut violations = vec![];
+    for row in rows {
+        let table: String = row.get(0)?;
+        let parent: String = row.get(2)?;
+        violations.push(format!("{table} -> {parent}"));
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Rows violate foreign keys after rebuilding the table:\n- {}",
+            violations.join("\n- ")
+        ))
+    }
+}
+
+/// Helper method to check whether SQLite needs to rebuild a table to apply an operation
+///
+/// Returns the name of the model to rebuild, if any.
+///
+/// - `db_impl`: [`DBImpl`]: The database implementation to use.
+/// - `models`: [`&[Model]`](Model): The models before the operation.
+/// - `operation`: [`&Operation`](Operation): The operation to check.
+fn sqlite_rebuild_target<'a>(
+    db_impl: DBImpl,
+    models: &[Model],
+    operation: &'a Operation,
+) -> Option<&'a str> {
+    let is_sqlite = match db_impl {
+        #[cfg(feature = "sqlite")]
+        DBImpl::SQLite => true,
+        #[allow(unreachable_patterns)]
+        _ => false,
+    };
+    if !is_sqlite {
+        return None;
+    }
+
+    match operation {
+        // SQLite can't add columns with constraints or non-constant defaults
+        Operation::CreateField { model, field } => {
+            let has_default = field
+                .annotations
+                .iter()
+                .any(|x| matches!(x, Annotation::DefaultValue(_)));
+            let needs_rebuild = field.annotations.iter().any(|x| match x {
+                Annotation::PrimaryKey
+                | Annotation::Unique
+                | Annotation::AutoIncrement
+                | Annotation::AutoCreateTime
+                | Annotation::AutoUpdateTime
+                | Annotation::ForeignKey(_) => true,
+                Annotation::NotNull => !has_default,
+                _ => false,
+            });
+
+            needs_rebuild.then_some(model.as_str())
+        }
+        // SQLite can't drop columns that are part of a constraint or an index
+        Operation::DeleteField { model, name } => models
+            .iter()
+            .find(|x| x.name == *model)
+            .and_then(|x| x.fields.iter().find(|y| y.name == *name))
+            .filter(|x| {
+                x.annotations.iter().any(|y| {
+                    matches!(
+                        y,
+                        Annotation::PrimaryKey
+                            | Annotation::Unique
+                            | Annotation::Index(_)
+                            | Annotation::ForeignKey(_)
+                    )
+                })
+            })
+            .map(|_| model.as_str()),
+        _ => None,
+    }
+}
+
+/// Helper method to build the statements creating a table
+///
+/// The first statement creates the table, the remaining ones create its indexes and triggers.
+///
+/// - `db_impl`: [`DBImpl`]: The database implementation to use.
+/// - `name`: [`&str`]: Name of the table.
+/// - `fields`: [`&[Field]`](Field): The fields of the table.
+fn create_table_statements(
+    db_impl: DBImpl,
+    name: &str,
+    fields: &[Field],
+) -> anyhow::Result<Vec<String>> {
+    let mut create_table = db_impl.create_table(name);
+
+    for field in fields {
+        create_table = create_table.add_column(db_impl.create_column(
+            name,
+            field.name.as_str(),
+            field.db_type,
+            &field.annotations,
+        ));
+    }
+
+    create_table
+        .build()?
+        .into_iter()
+        .map(|(query_string, query_bind_params)| {
+            if query_bind_params.is_empty() {
+                Ok(query_string)
+            } else {
+                Err(anyhow!(
+                    "Can not rebuild table {name} as its definition requires bind parameters"
+                ))
             }
-            #[allow(unused_variables)]
-            Operation::RawSQL {
-                mysql,
-                postgres,
-                sqlite,
-                ..
-            } => match db_impl {
-                #[cfg(feature = "sqlite")]
-                DBImpl::SQLite => {
-                    execute_statement(tx, sqlite.clone(), Vec::new(), do_log).await?;
-                }
-                #[cfg(feature = "postgres")]
-                DBImpl::Postgres => {
-                    execute_statement(tx, postgres.clone(), Vec::new(), do_log).await?;
-                }
-                #[cfg(feature = "mysql")]
-                DBImpl::MySQL => {
-                    execute_statement(tx, mysql.clone(), Vec::new(), do_log).await?;
-                }
-            },
+        })
+        .collect()
+}
+
+/// Helper method to rebuild a SQLite table with a changed definition
+///
+/// A new table is created from the model after the operation, the data of the common
+/// columns is copied, the old table is dropped and the new one is renamed. Indexes and
+/// triggers are recreated after the rename.
+///
+/// Dropping the old table would delete or update rows referencing it, so foreign keys must be
+/// disabled before the transaction is started, see [with_sqlite_foreign_keys_disabled].
+///
+/// - `db_impl`: [`DBImpl`]: The database implementation to use.
+/// - `before`: [`&Model`](Model): The model before the operation.
+/// - `after`: [`&Model`](Model): The model after the operation.
+pub fn rebuild_sqlite_table<'a>(
+    db_impl: DBImpl,
+    before: &Model,
+    after: &Model,
+) -> anyhow::Result<Vec<(String, Vec<Value<'a>>)>> {
+    let temporary = format!("_rorm_rebuild_{}", after.name);
+
+    let mut statements = vec![];
+    statements.extend(
+        create_table_statements(db_impl, temporary.as_str(), &after.fields)?
+            .into_iter()
+            .take(1),
+    );
+
+    let columns = after
+        .fields
+        .iter()
+        .filter(|x| before.fields.iter().any(|y| y.name == x.name))
+        .map(|x| format!("\"{}\"", x.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !columns.is_empty() {
+        statements.push(format!(
+            "INSERT INTO \"{temporary}\" ({columns}) SELECT {columns} FROM \"{}\";",
+            before.name
+        ));
+    }
+
+    statements.push(db_impl.drop_table(before.name.as_str()).build());
+    statements.extend(
+        db_impl
+            .alter_table(
+                temporary.as_str(),
+                AlterTableOperation::RenameTo {
+                    name: after.name.clone(),
+                },
+            )
+            .build()?
+            .into_iter()
+            .map(|(query_string, _)| query_string),
+    );
+
+    statements.extend(
+        create_table_statements(db_impl, after.name.as_str(), &after.fields)?
+            .into_iter()
+            .skip(1),
+    );
+
+    Ok(statements.into_iter().map(|x| (x, Vec::new())).collect())
+}
+
+/// Helper method to convert a migration to a transaction string
+///
+/// - `db_impl`: [`DBImpl`]: The database implementation to use.
+/// - `migration`: [`&Migration`](Migration): Reference to the migration that should be converted.
+/// - `models`: [`Option<&[Model]>`](Model): The models before the migration, if known.
+pub async fn migration_to_sql<'a>(
+    tx: &'a mut Transaction,
+    db_impl: DBImpl,
+    migration: &'a Migration,
+    models: Option<&[Model]>,
+    do_log: bool,
+) -> anyhow::Result<()> {
+    for (query_string, query_bind_params) in migration_to_statements(db_impl, migration, models)? {
+        execute_statement(tx, query_string, query_bind_params, do_log).await?;
+    }
+
+    Ok(())
+}
+
+/// Helper method to print the statements of a migration instead of executing them
+///
+/// - `db_impl`: [`DBImpl`]: The database implementation to use.
+/// - `migration`: [`&Migration`](Migration): Reference to the migration that should be printed.
+/// - `models`: [`Option<&[Model]>`](Model): The models before the migration, if known.
+pub fn print_migration_sql(
+    db_impl: DBImpl,
+    migration: &Migration,
+    models: Option<&[Model]>,
+) -> anyhow::Result<()> {
+    let mut statements = migration_to_statements(db_impl, migration, models)?;
+    if rebuilds_sqlite_table(db_impl, migration, models) {
+        statements.insert(0, ("PRAGMA foreign_keys = OFF;".to_string(), Vec::new()));
+        statements.push(("PRAGMA foreign_key_check;".to_string(), Vec::new()));
+        statements.push(("PRAGMA foreign_keys = ON;".to_string(), Vec::new()));
+    }
+
+    let mut text = format!("-- Migration {:04}_{}", migration.id, migration.name);
+    for (query_string, query_bind_params) in &statements {
+        text.push_str(&format!("\n{query_string}"));
+        if !query_bind_params.is_empty() {
+            text.push_str(&format!("\n-- Bind parameters: {query_bind_params:?}"));
         }
     }
 
+    report(
+        Event::MigrationSql {
+            id: migration.id,
+            name: migration.name.as_str(),
+            statements: statements.iter().map(|(x, _)| x.as_str()).collect(),
+        },
+        text,
+    );
+
     Ok(())
 }
 
@@ -143,7 +561,141 @@ async fn execute_statement(
     do_log: bool,
 ) -> Result<(), rorm_db::Error> {
     if do_log {
-        println!("{}", query_string.as_str());
+        print_sql(&query_string);
     }
     tx.execute::<Nothing>(query_string, query_bind_params).await
 }
+
+#[cfg(test)]
+#[cfg(feature = "sqlite")]
+mod test {
+    use rorm_declaration::imr::{Annotation, DbType, DefaultValue, Field, ForeignKey, Model};
+    use rorm_declaration::migration::Operation;
+    use rorm_sql::DBImpl;
+
+    use crate::migrate::sql_builder::{rebuild_sqlite_table, sqlite_rebuild_target};
+
+    fn field(name: &str, annotations: Vec<Annotation>) -> Field {
+        Field {
+            name: name.to_string(),
+            db_type: DbType::Int64,
+            annotations,
+            source_defined_at: None,
+        }
+    }
+
+    fn model(fields: Vec<Field>) -> Model {
+        Model {
+            name: "foo".to_string(),
+            fields,
+            source_defined_at: None,
+        }
+    }
+
+    #[test]
+    fn test_sqlite_rebuild_target() {
+        let models = vec![model(vec![
+            field("id", vec![Annotation::PrimaryKey]),
+            field("bar", vec![Annotation::Unique]),
+            field("baz", vec![]),
+        ])];
+        let create_field = |annotations| Operation::CreateField {
+            model: "foo".to_string(),
+            field: field("new", annotations),
+        };
+        let delete_field = |name: &str| Operation::DeleteField {
+            model: "foo".to_string(),
+            name: name.to_string(),
+        };
+        let target = |operation: Operation| {
+            sqlite_rebuild_target(DBImpl::SQLite, &models, &operation).map(str::to_string)
+        };
+
+        assert_eq!(target(create_field(vec![])), None);
+        assert_eq!(
+            target(create_field(vec![
+                Annotation::NotNull,
+                Annotation::DefaultValue(DefaultValue::Integer(0)),
+            ])),
+            None
+        );
+        assert_eq!(
+            target(create_field(vec![Annotation::NotNull])),
+            Some("foo".to_string())
+        );
+        assert_eq!(
+            target(create_field(vec![Annotation::Unique])),
+            Some("foo".to_string())
+        );
+        assert_eq!(
+            target(create_field(vec![Annotation::ForeignKey(ForeignKey {
+                table_name: "qux".to_string(),
+                column_name: "id".to_string(),
+                ..Default::default()
+            })])),
+            Some("foo".to_string())
+        );
+
+        assert_eq!(target(delete_field("baz")), None);
+        assert_eq!(target(delete_field("bar")), Some("foo".to_string()));
+        // Unknown fields are left to ALTER TABLE
+        assert_eq!(target(delete_field("unknown")), None);
+
+        assert_eq!(
+            target(Operation::RenameField {
+                table_name: "foo".to_string(),
+                old: "bar".to_string(),
+                new: "qux".to_string(),
+            }),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "postgres")]
+    fn test_sqlite_rebuild_target_other_dialect() {
+        let models = vec![model(vec![field("id", vec![Annotation::PrimaryKey])])];
+        let operation = Operation::CreateField {
+            model: "foo".to_string(),
+            field: field("new", vec![Annotation::Unique]),
+        };
+
+        assert_eq!(
+            sqlite_rebuild_target(DBImpl::Postgres, &models, &operation),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rebuild_sqlite_table() {
+        let before = model(vec![
+            field("id", vec![Annotation::PrimaryKey]),
+            field("bar", vec![]),
+        ]);
+        let after = model(vec![
+            field("id", vec![Annotation::PrimaryKey]),
+            field("baz", vec![Annotation::Unique]),
+        ]);
+
+        let statements: Vec<String> = rebuild_sqlite_table(DBImpl::SQLite, &before, &after)
+            .unwrap()
+            .into_iter()
+            .map(|(query_string, bind_params)| {
+                assert!(bind_params.is_empty());
+                query_string
+            })
+            .collect();
+
+        assert!(statements[0].starts_with("CREATE TABLE"));
+        assert!(statements[0].contains("_rorm_rebuild_foo"));
+        // Only the columns of both models are copied
+        assert_eq!(
+            statements[1],
+            r#"INSERT INTO "_rorm_rebuild_foo" ("id") SELECT "id" FROM "foo";"#
+        );
+        assert!(statements[2].starts_with("DROP TABLE"));
+        assert!(statements[3].contains("RENAME TO"));
+        // Foreign keys can't be disabled within the transaction the statements run in
+        assert!(statements.iter().all(|x| !x.contains("PRAGMA")));
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::migrate::config::deserialize_db_conf;
+use crate::migrate::{
+    applied_migration_ids, connect_database, get_applied_migrations_read_only,
+    last_migration_table_name, AppliedMigration,
+};
+use crate::print_message;
+use crate::utils::migrations::{
+    get_all_existing_migrations, get_existing_migrations, substitute_squashed_migrations,
+};
+use crate::utils::output::{report, Event};
+
+/**
+Lists all migrations and whether they have been applied to the database.
+
+Applied migrations that can not be found in the migration directory are listed as well.
+*/
+pub async fn show_migrations(
+    migration_dir: String,
+    database_config: String,
+    log_sql: bool,
+) -> anyhow::Result<()> {
+    let p = Path::new(migration_dir.as_str());
+    if !p.exists() || p.is_file() {
+        print_message!(
+            "Couldn't find the migration directory in {} \n\n\
+            You can specify an alternative path with --migration-dir <PATH>",
+            migration_dir.as_str()
+        );
+        return Ok(());
+    }
+
+    let db_conf = deserialize_db_conf(Path::new(database_config.as_str()))?;
+
+    let existing_migrations = get_existing_migrations(migration_dir.as_str())
+        .with_context(|| "Couldn't retrieve existing migrations")?;
+
+    let pool = connect_database(&db_conf).await?;
+    let applied =
+        get_applied_migrations_read_only(&pool, last_migration_table_name(&db_conf), log_sql)
+            .await?;
+
+    let applied_ids = applied_migration_ids(
+        &applied,
+        &get_all_existing_migrations(migration_dir.as_str())?,
+    );
+
+    let last_applied = existing_migrations
+        .iter()
+        .rev()
+        .find(|x| applied_ids.contains(&x.id))
+        .map(|x| x.id);
+    let existing_migrations = substitute_squashed_migrations(
+        migration_dir.as_str(),
+        existing_migrations,
+        last_applied,
+        None,
+    )
+    .with_context(|| "Couldn't retrieve squashed migrations")?;
+
+    if existing_migrations.is_empty() {
+        let message = "No migrations found.";
+        report(Event::NothingToDo { message }, message);
+    }
+
+    for migration in &existing_migrations {
+        let last_row = applied.iter().rev().find(|x| x.id == migration.id);
+        let applied_at = last_row.and_then(|x| x.applied_at.as_ref());
+
+        if let Some(applied_operations) = last_row
+            .and_then(|x| x.applied_operations)
+            .filter(|_| !applied_ids.contains(&migration.id))
+        {
+            report(
+                Event::MigrationStatus {
+                    id: migration.id,
+                    name: Some(migration.name.as_str()),
+                    status: "partially_applied",
+                    applied_at: None,
+                    applied_operations: Some(applied_operations),
+                },
+                format!(
+                    "[~] {:04}_{} ({applied_operations} of {} operations applied)",
+                    migration.id,
+                    migration.name,
+                    migration.operations.len()
+                ),
+            );
+        } else if applied_ids.contains(&migration.id) {
+            report(
+                Event::MigrationStatus {
+                    id: migration.id,
+                    name: Some(migration.name.as_str()),
+                    status: "applied",
+                    applied_at: applied_at.map(|x| x.as_str()),
+                    applied_operations: None,
+                },
+                match applied_at {
+                    Some(applied_at) => format!(
+                        "[X] {:04}_{} (applied at {applied_at})",
+                        migration.id, migration.name
+                    ),
+                    None => format!("[X] {:04}_{}", migration.id, migration.name),
+                },
+            );
+        } else {
+            report(
+                Event::MigrationStatus {
+                    id: migration.id,
+                    name: Some(migration.name.as_str()),
+                    status: "pending",
+                    applied_at: None,
+                    applied_operations: None,
+                },
+                format!("[ ] {:04}_{}", migration.id, migration.name),
+            );
+        }
+    }
+
+    let unknown: Vec<&AppliedMigration> = applied
+        .iter()
+        .filter(|x| {
+            !existing_migrations
+                .iter()
+                .any(|y| y.id == x.id || y.replaces.contains(&x.id))
+        })
+        .collect();
+
+    if !unknown.is_empty() {
+        print_message!("\nApplied migrations that were not found in the migration directory:");
+        for migration in unknown {
+            let id = migration.id;
+            report(
+                Event::MigrationStatus {
+                    id,
+                    name: migration.name.as_deref(),
+                    status: "unknown",
+                    applied_at: migration.applied_at.as_deref(),
+                    applied_operations: migration.applied_operations,
+                },
+                match &migration.applied_at {
+                    Some(applied_at) => format!("[?] {id:04} (applied at {applied_at})"),
+                    None => format!("[?] {id:04}"),
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
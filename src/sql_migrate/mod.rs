@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+
+use crate::migrate::config::deserialize_db_conf;
+use crate::migrate::sql_builder::{dialect_by_name, dialect_of_driver, print_migration_sql};
+use crate::print_message;
+use crate::utils::migrations::{convert_migrations_to_internal_models, get_existing_migrations};
+
+/**
+Prints the SQL statements of a migration without connecting to the database.
+
+If no dialect is given, the dialect of the driver in the database configuration is used.
+*/
+pub fn sql_migrate(
+    migration_dir: String,
+    database_config: String,
+    migration_id: u16,
+    dialect: Option<String>,
+) -> anyhow::Result<()> {
+    let p = Path::new(migration_dir.as_str());
+    if !p.exists() || p.is_file() {
+        print_message!(
+            "Couldn't find the migration directory in {} \n\n\
+            You can specify an alternative path with --migration-dir <PATH>",
+            migration_dir.as_str()
+        );
+        return Ok(());
+    }
+
+    let db_impl = match dialect {
+        Some(dialect) => dialect_by_name(dialect.as_str())?,
+        None => {
+            let db_conf = deserialize_db_conf(Path::new(database_config.as_str()))
+                .with_context(|| "Specify the dialect with --dialect <DIALECT> instead")?;
+            dialect_of_driver(&db_conf.driver)
+        }
+    };
+
+    let migrations = get_existing_migrations(migration_dir.as_str())
+        .with_context(|| "Couldn't retrieve existing migrations")?;
+
+    let idx = migrations
+        .iter()
+        .position(|x| x.id == migration_id)
+        .ok_or_else(|| anyhow!("Migration {migration_id:04} was not found."))?;
+
+    let models = convert_migrations_to_internal_models(&migrations[..idx])
+        .ok()
+        .map(|x| x.models);
+
+    print_migration_sql(db_impl, &migrations[idx], models.as_deref())
+}
@@ -1,7 +1,261 @@
 use std::path::Path;
 
-use crate::utils::migrations::get_existing_migrations;
+use anyhow::Context;
+use rorm_declaration::imr::{Annotation, Field};
+use rorm_declaration::migration::{Migration, Operation};
 
+use crate::print_message;
+use crate::utils::migrations::{
+    convert_migration_to_file, get_existing_migrations, is_atomic, mark_non_atomic, record_checksum,
+};
+use crate::utils::output::{report, Event};
+
+/// Checks whether the operation touches the model `model` at all.
+fn touches_model(operation: &Operation, model: &str) -> bool {
+    match operation {
+        Operation::CreateModel { name, .. } | Operation::DeleteModel { name } => name == model,
+        Operation::RenameModel { old, new } => old == model || new == model,
+        Operation::CreateField { model: m, .. } | Operation::DeleteField { model: m, .. } => {
+            m == model
+        }
+        Operation::RenameField { table_name, .. } => table_name == model,
+        Operation::RawSQL { .. } => false,
+    }
+}
+
+/// Checks whether the operation touches the field `field` of the model `model`.
+///
+/// Operations on the model itself are considered to touch all of its fields.
+fn touches_field(operation: &Operation, model: &str, field: &str) -> bool {
+    match operation {
+        Operation::CreateModel { name, .. } | Operation::DeleteModel { name } => name == model,
+        Operation::RenameModel { old, new } => old == model || new == model,
+        Operation::CreateField { model: m, field: f } => m == model && f.name == field,
+        Operation::DeleteField { model: m, name } => m == model && name == field,
+        Operation::RenameField {
+            table_name,
+            old,
+            new,
+        } => table_name == model && (old == field || new == field),
+        Operation::RawSQL { .. } => false,
+    }
+}
+
+/// Checks whether the operation makes the table `table` (or its column `column`)
+/// available under this name.
+fn introduces(operation: &Operation, table: &str, column: &str) -> bool {
+    match operation {
+        Operation::CreateModel { name, .. } => name == table,
+        Operation::RenameModel { new, .. } => new == table,
+        Operation::CreateField { model, field } => model == table && field.name == column,
+        Operation::RenameField {
+            table_name, new, ..
+        } => table_name == table && new == column,
+        _ => false,
+    }
+}
+
+/// Checks whether any field created by the operations references the table `table`
+/// (or its column `column`, if specified) with a foreign key.
+fn is_referenced(operations: &[Operation], table: &str, column: Option<&str>) -> bool {
+    operations.iter().any(|operation| {
+        let fields: Vec<&Field> = match operation {
+            Operation::CreateModel { fields, .. } => fields.iter().collect(),
+            Operation::CreateField { field, .. } => vec![field],
+            _ => vec![],
+        };
+
+        fields
+            .iter()
+            .flat_map(|field| field.annotations.iter())
+            .any(|annotation| match annotation {
+                Annotation::ForeignKey(fk) => {
+                    fk.table_name == table
+                        && match column {
+                            None => true,
+                            Some(column) => fk.column_name == column,
+                        }
+                }
+                _ => false,
+            })
+    })
+}
+
+/**
+Collapses a list of operations into the smallest equivalent list of operations.
+
+Operations are only merged with preceding operations if no other operation in between
+depends on them. [Operation::RawSQL] acts as a barrier, no operation is moved across it.
+
+`operations`: [Vec<Operation>]: The operations in the order they would be applied.
+*/
+pub fn squash_operations(operations: Vec<Operation>) -> Vec<Operation> {
+    let mut squashed: Vec<Operation> = vec![];
+    // Operations before this index must not be altered
+    let mut barrier = 0;
+
+    for operation in operations {
+        match operation {
+            Operation::CreateField { model, field } => {
+                let idx = squashed[barrier..]
+                    .iter()
+                    .rposition(|x| touches_field(x, &model, &field.name))
+                    .map(|x| x + barrier);
+
+                // A referenced column must exist before the model is created
+                let target_exists = |idx: usize| {
+                    field.annotations.iter().all(|annotation| match annotation {
+                        Annotation::ForeignKey(fk) => !squashed[idx + 1..]
+                            .iter()
+                            .any(|x| introduces(x, &fk.table_name, &fk.column_name)),
+                        _ => true,
+                    })
+                };
+
+                if let Some(idx) = idx {
+                    if matches!(&squashed[idx], Operation::CreateModel { name, .. } if *name == model)
+                        && target_exists(idx)
+                    {
+                        if let Operation::CreateModel { fields, .. } = &mut squashed[idx] {
+                            fields.push(field);
+                        }
+                        continue;
+                    }
+                }
+
+                squashed.push(Operation::CreateField { model, field });
+            }
+            Operation::RenameModel { old, new } => {
+                let idx = squashed[barrier..]
+                    .iter()
+                    .rposition(|x| touches_model(x, &old))
+                    .map(|x| x + barrier);
+
+                if let Some(idx) = idx {
+                    if matches!(&squashed[idx], Operation::CreateModel { name, .. } if *name == old)
+                        && !squashed[idx + 1..].iter().any(|x| touches_model(x, &new))
+                        && !is_referenced(&squashed[barrier..], &old, None)
+                    {
+                        if let Operation::CreateModel { name, .. } = &mut squashed[idx] {
+                            *name = new;
+                        }
+                        continue;
+                    }
+                }
+
+                squashed.push(Operation::RenameModel { old, new });
+            }
+            Operation::DeleteModel { name } => {
+                let idx = squashed[barrier..]
+                    .iter()
+                    .rposition(
+                        |x| matches!(x, Operation::CreateModel { name: n, .. } if *n == name),
+                    )
+                    .map(|x| x + barrier);
+
+                if let Some(idx) = idx {
+                    let renamed = squashed[idx + 1..].iter().any(|x| {
+                        matches!(x, Operation::RenameModel { .. }) && touches_model(x, &name)
+                    });
+
+                    let remaining: Vec<Operation> = squashed[barrier..]
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, x)| *i + barrier < idx || !touches_model(x, &name))
+                        .map(|(_, x)| x.clone())
+                        .collect();
+
+                    if !renamed && !is_referenced(&remaining, &name, None) {
+                        squashed.truncate(barrier);
+                        squashed.extend(remaining);
+                        continue;
+                    }
+                }
+
+                squashed.push(Operation::DeleteModel { name });
+            }
+            Operation::RenameField {
+                table_name,
+                old,
+                new,
+            } => {
+                let idx = squashed[barrier..]
+                    .iter()
+                    .rposition(|x| touches_field(x, &table_name, &old))
+                    .map(|x| x + barrier);
+
+                if let Some(idx) = idx {
+                    if !squashed[idx + 1..]
+                        .iter()
+                        .any(|x| touches_field(x, &table_name, &new))
+                        && !is_referenced(&squashed[barrier..], &table_name, Some(&old))
+                    {
+                        match &mut squashed[idx] {
+                            Operation::CreateField { field, .. } => {
+                                field.name = new;
+                                continue;
+                            }
+                            Operation::CreateModel { name, fields } if *name == table_name => {
+                                if let Some(field) = fields.iter_mut().find(|x| x.name == old) {
+                                    field.name = new;
+                                    continue;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                squashed.push(Operation::RenameField {
+                    table_name,
+                    old,
+                    new,
+                });
+            }
+            Operation::DeleteField { model, name } => {
+                let idx = squashed[barrier..]
+                    .iter()
+                    .rposition(|x| touches_field(x, &model, &name))
+                    .map(|x| x + barrier);
+
+                if let Some(idx) = idx {
+                    if !is_referenced(&squashed[barrier..], &model, Some(&name)) {
+                        if matches!(&squashed[idx], Operation::CreateField { .. }) {
+                            squashed.remove(idx);
+                            continue;
+                        }
+
+                        if let Operation::CreateModel { name: n, fields } = &mut squashed[idx] {
+                            if *n == model
+                                && fields.len() > 1
+                                && fields.iter().any(|x| x.name == name)
+                            {
+                                fields.retain(|x| x.name != name);
+                                continue;
+                            }
+                        }
+                    }
+                }
+
+                squashed.push(Operation::DeleteField { model, name });
+            }
+            Operation::CreateModel { .. } => squashed.push(operation),
+            Operation::RawSQL { .. } => {
+                squashed.push(operation);
+                barrier = squashed.len();
+            }
+        }
+    }
+
+    squashed
+}
+
+/**
+Squashes the migrations from `first_migration` to `last_migration` (inclusive) into one migration.
+
+The new migration replaces the squashed migrations and takes the id of `last_migration`,
+so following migrations can still depend on it.
+*/
 pub async fn squash_migrations(
     migration_dir: String,
     first_migration: u16,
@@ -9,7 +263,7 @@ pub async fn squash_migrations(
 ) -> anyhow::Result<()> {
     let p = Path::new(migration_dir.as_str());
     if !p.exists() || p.is_file() {
-        println!(
+        print_message!(
             "Couldn't find the migration directory in {} \n\n\
             You can specify an alternative path with --migration-dir <PATH>",
             migration_dir.as_str()
@@ -18,32 +272,189 @@ pub async fn squash_migrations(
     }
     let migrations = get_existing_migrations(&migration_dir)?;
 
-    let [mut first, mut last] = [false; 2];
-    for migration in migrations {
-        if migration.id == first_migration {
-            first = true;
-        } else if migration.id == last_migration {
-            last = true;
-        }
-    }
-
-    if !first {
-        println!(
+    let Some(first) = migrations.iter().position(|x| x.id == first_migration) else {
+        print_message!(
             "Could not find migration {first_migration}.\n\n\
             This could be due to a missing migration or because the \n\
             migration is currently a squashed migration"
         );
         return Ok(());
-    }
+    };
 
-    if !last {
-        println!(
+    let Some(last) = migrations.iter().position(|x| x.id == last_migration) else {
+        print_message!(
             "Could not find migration {last_migration}.\n\n\
             This could be due to a missing migration or because the \n\
             migration is currently a squashed migration"
         );
         return Ok(());
+    };
+
+    if first >= last {
+        let message = format!(
+            "Migration {first_migration:04} has to be applied before migration {last_migration:04}.\n\
+            Nothing to squash."
+        );
+        report(Event::NothingToDo { message: &message }, &message);
+        return Ok(());
+    }
+
+    let to_squash = &migrations[first..=last];
+
+    // A single non-atomic migration makes the squashed migration non-atomic
+    let mut atomic = true;
+    for migration in to_squash {
+        atomic &= is_atomic(&migration_dir, migration)?;
     }
 
-    unimplemented!("coming soon!");
+    let operations = squash_operations(
+        to_squash
+            .iter()
+            .flat_map(|x| x.operations.iter().cloned())
+            .collect(),
+    );
+
+    let migration = Migration {
+        hash: migrations[last].hash.clone(),
+        initial: migrations[first].initial,
+        id: last_migration,
+        name: format!("squashed_{first_migration:04}_{last_migration:04}"),
+        dependency: migrations[first].dependency,
+        replaces: to_squash.iter().map(|x| x.id).collect(),
+        operations,
+    };
+
+    let path = p.join(format!("{:04}_{}.toml", migration.id, &migration.name));
+    let operation_count: usize = to_squash.iter().map(|x| x.operations.len()).sum();
+    print_message!(
+        "Squashed {} operations of {} migrations into {} operations.",
+        operation_count,
+        to_squash.len(),
+        migration.operations.len()
+    );
+
+    record_checksum(&migration_dir, &migration)?;
+    convert_migration_to_file(migration, &path)
+        .with_context(|| "Error occurred while converting migration to file")?;
+    if !atomic {
+        mark_non_atomic(&path)?;
+    }
+
+    report(
+        Event::FileWritten {
+            path: &path.display().to_string(),
+        },
+        format!("Written squashed migration to {}", path.display()),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rorm_declaration::imr::{Annotation, DbType, Field};
+    use rorm_declaration::migration::Operation;
+
+    use crate::squash_migrations::squash_operations;
+
+    fn field(name: &str) -> Field {
+        Field {
+            name: name.to_string(),
+            db_type: DbType::Int64,
+            annotations: vec![Annotation::NotNull],
+            source_defined_at: None,
+        }
+    }
+
+    #[test]
+    fn test_squash_create_field_into_model() {
+        let squashed = squash_operations(vec![
+            Operation::CreateModel {
+                name: "foo".to_string(),
+                fields: vec![field("id")],
+            },
+            Operation::CreateField {
+                model: "foo".to_string(),
+                field: field("bar"),
+            },
+            Operation::RenameField {
+                table_name: "foo".to_string(),
+                old: "bar".to_string(),
+                new: "baz".to_string(),
+            },
+        ]);
+
+        assert_eq!(squashed.len(), 1);
+        assert!(matches!(
+            &squashed[0],
+            Operation::CreateModel { name, fields }
+                if name == "foo" && fields == &vec![field("id"), field("baz")]
+        ));
+    }
+
+    #[test]
+    fn test_squash_create_and_delete_model() {
+        let squashed = squash_operations(vec![
+            Operation::CreateModel {
+                name: "foo".to_string(),
+                fields: vec![field("id")],
+            },
+            Operation::RenameModel {
+                old: "foo".to_string(),
+                new: "bar".to_string(),
+            },
+            Operation::CreateField {
+                model: "bar".to_string(),
+                field: field("baz"),
+            },
+            Operation::DeleteModel {
+                name: "bar".to_string(),
+            },
+        ]);
+
+        assert!(squashed.is_empty());
+    }
+
+    #[test]
+    fn test_squash_existing_model() {
+        let squashed = squash_operations(vec![
+            Operation::CreateField {
+                model: "foo".to_string(),
+                field: field("bar"),
+            },
+            Operation::DeleteField {
+                model: "foo".to_string(),
+                name: "bar".to_string(),
+            },
+            Operation::RenameModel {
+                old: "foo".to_string(),
+                new: "baz".to_string(),
+            },
+        ]);
+
+        assert_eq!(squashed.len(), 1);
+        assert!(matches!(&squashed[0], Operation::RenameModel { .. }));
+    }
+
+    #[test]
+    fn test_squash_raw_sql_barrier() {
+        let squashed = squash_operations(vec![
+            Operation::CreateModel {
+                name: "foo".to_string(),
+                fields: vec![field("id")],
+            },
+            Operation::RawSQL {
+                structure_safe: true,
+                sqlite: "".to_string(),
+                mysql: "".to_string(),
+                postgres: "".to_string(),
+            },
+            Operation::CreateField {
+                model: "foo".to_string(),
+                field: field("bar"),
+            },
+        ]);
+
+        assert_eq!(squashed.len(), 3);
+    }
 }
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use rorm_db::executor::{All, Executor};
+use rorm_db::Database;
+use rorm_sql::DBImpl;
+
+use crate::log_sql;
+
+/// A column of a table as found in the database
+#[derive(Debug, Clone)]
+pub struct DbColumn {
+    /// Name of the column
+    pub name: String,
+    /// Data type as reported by the database
+    pub data_type: String,
+    /// Maximum length of character columns, if reported by the database
+    pub max_length: Option<i64>,
+    /// Whether the column accepts null values
+    pub nullable: bool,
+    /// Whether the column is (part of) the primary key
+    pub primary_key: bool,
+    /// Whether a unique constraint exists for exactly this column
+    pub unique: bool,
+    /// Whether values are generated by the database
+    pub auto_increment: bool,
+    /// Table and column referenced by a foreign key
+    pub foreign_key: Option<(String, String)>,
+}
+
+/// A table as found in the database
+#[derive(Debug, Clone)]
+pub struct DbTable {
+    /// Name of the table
+    pub name: String,
+    /// Columns of the table in the order they are defined in
+    pub columns: Vec<DbColumn>,
+}
+
+/// Quotes a value to be used as string literal in a query
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Key constraints of a table: Name of the constraint -> (primary key, columns)
+type KeyConstraints = HashMap<String, (bool, Vec<String>)>;
+
+/// Applies the key constraints and foreign keys to the columns of a table
+fn apply_constraints(
+    columns: &mut [DbColumn],
+    keys: KeyConstraints,
+    foreign_keys: Vec<(String, String, String)>,
+) {
+    for (primary_key, key_columns) in keys.into_values() {
+        for column in columns.iter_mut() {
+            if key_columns.contains(&column.name) {
+                if primary_key {
+                    column.primary_key = true;
+                } else if key_columns.len() == 1 {
+                    column.unique = true;
+                }
+            }
+        }
+    }
+
+    for (column_name, table, referenced) in foreign_keys {
+        if let Some(column) = columns.iter_mut().find(|x| x.name == column_name) {
+            column.foreign_key = Some((table, referenced));
+        }
+    }
+}
+
+/**
+Reads the tables of the connected database.
+
+Internal tables of the database are skipped.
+
+- `db`: [`&Database`](Database): Database to inspect.
+- `log_sql`: [`bool`]: Log all SQL statements.
+*/
+pub async fn introspect_database(db: &Database, log_sql: bool) -> anyhow::Result<Vec<DbTable>> {
+    let tables = match db.dialect() {
+        #[cfg(feature = "sqlite")]
+        DBImpl::SQLite => introspect_sqlite(db, log_sql).await,
+        #[cfg(feature = "mysql")]
+        DBImpl::MySQL => introspect_mysql(db, log_sql).await,
+        #[cfg(feature = "postgres")]
+        DBImpl::Postgres => introspect_postgres(db, log_sql).await,
+    };
+
+    tables.with_context(|| "Couldn't read the schema of the database")
+}
+
+/**
+Reads the names of the columns of a table.
+
+- `db`: [`&Database`](Database): Database to inspect.
+- `table_name`: [`&str`]: Name of the table.
+- `log_sql`: [`bool`]: Log all SQL statements.
+*/
+pub async fn get_column_names(
+    db: &Database,
+    table_name: &str,
+    log_sql: bool,
+) -> anyhow::Result<Vec<String>> {
+    let query = match db.dialect() {
+        #[cfg(feature = "sqlite")]
+        DBImpl::SQLite => format!("SELECT name FROM pragma_table_info({});", quote(table_name)),
+        #[cfg(feature = "mysql")]
+        DBImpl::MySQL => format!(
+            "SELECT CAST(column_name AS CHAR) FROM information_schema.columns WHERE table_schema = DATABASE() AND table_name = {};",
+            quote(table_name)
+        ),
+        #[cfg(feature = "postgres")]
+        DBImpl::Postgres => format!(
+            "SELECT CAST(column_name AS TEXT) FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = {};",
+            quote(table_name)
+        ),
+    };
+
+    let rows = db
+        .execute::<All>(log_sql!(query, log_sql), Vec::new())
+        .await
+        .with_context(|| format!("Couldn't read the columns of table {table_name}"))?;
+
+    let mut columns = vec![];
+    for row in rows {
+        let name: String = row.get(0)?;
+        columns.push(name);
+    }
+
+    Ok(columns)
+}
+
+#[cfg(feature = "sqlite")]
+async fn introspect_sqlite(db: &Database, log_sql: bool) -> anyhow::Result<Vec<DbTable>> {
+    let mut tables = vec![];
+
+    let rows = db
+        .execute::<All>(
+            log_sql!(
+                "SELECT name, sql FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;"
+                    .to_string(),
+                log_sql
+            ),
+            Vec::new(),
+        )
+        .await?;
+
+    for row in rows {
+        let name: String = row.get(0)?;
+        let sql: Option<String> = row.get(1)?;
+        // Only an INTEGER PRIMARY KEY can be declared as AUTOINCREMENT
+        let autoincrement = sql.is_some_and(|x| x.to_uppercase().contains("AUTOINCREMENT"));
+
+        let mut columns = vec![];
+        let mut keys = KeyConstraints::new();
+        let rows = db
+            .execute::<All>(
+                log_sql!(
+                    format!(
+                        r#"SELECT name, type, "notnull", pk FROM pragma_table_info({});"#,
+                        quote(&name)
+                    ),
+                    log_sql
+                ),
+                Vec::new(),
+            )
+            .await?;
+        for row in rows {
+            let column_name: String = row.get(0)?;
+            let data_type: String = row.get(1)?;
+            let not_null: i64 = row.get(2)?;
+            let primary_key: i64 = row.get(3)?;
+
+            if primary_key > 0 {
+                keys.entry("primary".to_string())
+                    .or_insert((true, vec![]))
+                    .1
+                    .push(column_name.clone());
+            }
+
+            // The length is only known from the declared type, e.g. VARCHAR(255)
+            let max_length = data_type
+                .split_once('(')
+                .and_then(|(_, x)| x.trim_end_matches(')').trim().parse().ok());
+            let auto_increment =
+                autoincrement && primary_key > 0 && data_type.eq_ignore_ascii_case("integer");
+
+            columns.push(DbColumn {
+                name: column_name,
+                data_type,
+                max_length,
+                nullable: not_null == 0 && primary_key == 0,
+                primary_key: false,
+                unique: false,
+                auto_increment,
+                foreign_key: None,
+            });
+        }
+
+        let rows = db
+            .execute::<All>(
+                log_sql!(
+                    format!(
+                        r#"SELECT il.name, ii.name FROM pragma_index_list({}) AS il, pragma_index_info(il.name) AS ii WHERE il."unique" = 1 AND il.origin != 'pk';"#,
+                        quote(&name)
+                    ),
+                    log_sql
+                ),
+                Vec::new(),
+            )
+            .await?;
+        for row in rows {
+            let index_name: String = row.get(0)?;
+            let column_name: String = row.get(1)?;
+            keys.entry(index_name)
+                .or_insert((false, vec![]))
+                .1
+                .push(column_name);
+        }
+
+        let mut foreign_keys = vec![];
+        let rows = db
+            .execute::<All>(
+                log_sql!(
+                    format!(
+                        r#"SELECT "from", "table", "to" FROM pragma_foreign_key_list({});"#,
+                        quote(&name)
+                    ),
+                    log_sql
+                ),
+                Vec::new(),
+            )
+            .await?;
+        for row in rows {
+            let column_name: String = row.get(0)?;
+            let table: String = row.get(1)?;
+            let referenced: Option<String> = row.get(2)?;
+            // Without a referenced column, the primary key is referenced
+            foreign_keys.push((column_name, table, referenced.unwrap_or_default()));
+        }
+
+        apply_constraints(&mut columns, keys, foreign_keys);
+        tables.push(DbTable { name, columns });
+    }
+
+    Ok(tables)
+}
+
+#[cfg(feature = "mysql")]
+async fn introspect_mysql(db: &Database, log_sql: bool) -> anyhow::Result<Vec<DbTable>> {
+    let mut tables = vec![];
+
+    let rows = db
+        .execute::<All>(
+            log_sql!(
+                "SELECT CAST(table_name AS CHAR) FROM information_schema.tables WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE' ORDER BY table_name;"
+                    .to_string(),
+                log_sql
+            ),
+            Vec::new(),
+        )
+        .await?;
+
+    for row in rows {
+        let name: String = row.get(0)?;
+
+        let mut columns = vec![];
+        let rows = db
+            .execute::<All>(
+                log_sql!(
+                    format!(
+                        "SELECT CAST(column_name AS CHAR), CAST(data_type AS CHAR), CAST(is_nullable AS CHAR), CAST(character_maximum_length AS SIGNED), CAST(extra AS CHAR) FROM information_schema.columns WHERE table_schema = DATABASE() AND table_name = {} ORDER BY ordinal_position;",
+                        quote(&name)
+                    ),
+                    log_sql
+                ),
+                Vec::new(),
+            )
+            .await?;
+        for row in rows {
+            let column_name: String = row.get(0)?;
+            let data_type: String = row.get(1)?;
+            let nullable: String = row.get(2)?;
+            let max_length: Option<i64> = row.get(3)?;
+            let extra: String = row.get(4)?;
+            let auto_increment = extra.contains("auto_increment");
+
+            columns.push(DbColumn {
+                name: column_name,
+                data_type,
+                max_length,
+                nullable: nullable == "YES",
+                primary_key: false,
+                unique: false,
+                auto_increment,
+                foreign_key: None,
+            });
+        }
+
+        let mut keys = KeyConstraints::new();
+        let rows = db
+            .execute::<All>(
+                log_sql!(
+                    format!(
+                        "SELECT CAST(index_name AS CHAR), CAST(column_name AS CHAR) FROM information_schema.statistics WHERE table_schema = DATABASE() AND table_name = {} AND non_unique = 0;",
+                        quote(&name)
+                    ),
+                    log_sql
+                ),
+                Vec::new(),
+            )
+            .await?;
+        for row in rows {
+            let index_name: String = row.get(0)?;
+            let column_name: String = row.get(1)?;
+            let primary_key = index_name == "PRIMARY";
+            keys.entry(index_name)
+                .or_insert((primary_key, vec![]))
+                .1
+                .push(column_name);
+        }
+
+        let mut foreign_keys = vec![];
+        let rows = db
+            .execute::<All>(
+                log_sql!(
+                    format!(
+                        "SELECT CAST(column_name AS CHAR), CAST(referenced_table_name AS CHAR), CAST(referenced_column_name AS CHAR) FROM information_schema.key_column_usage WHERE table_schema = DATABASE() AND table_name = {} AND referenced_table_name IS NOT NULL;",
+                        quote(&name)
+                    ),
+                    log_sql
+                ),
+                Vec::new(),
+            )
+            .await?;
+        for row in rows {
+            let column_name: String = row.get(0)?;
+            let table: String = row.get(1)?;
+            let referenced: String = row.get(2)?;
+            foreign_keys.push((column_name, table, referenced));
+        }
+
+        apply_constraints(&mut columns, keys, foreign_keys);
+        tables.push(DbTable { name, columns });
+    }
+
+    Ok(tables)
+}
+
+#[cfg(feature = "postgres")]
+async fn introspect_postgres(db: &Database, log_sql: bool) -> anyhow::Result<Vec<DbTable>> {
+    let mut tables = vec![];
+
+    let rows = db
+        .execute::<All>(
+            log_sql!(
+                "SELECT CAST(table_name AS TEXT) FROM information_schema.tables WHERE table_schema = current_schema() AND table_type = 'BASE TABLE' ORDER BY table_name;"
+                    .to_string(),
+                log_sql
+            ),
+            Vec::new(),
+        )
+        .await?;
+
+    for row in rows {
+        let name: String = row.get(0)?;
+
+        let mut columns = vec![];
+        let rows = db
+            .execute::<All>(
+                log_sql!(
+                    format!(
+                        "SELECT CAST(column_name AS TEXT), CAST(data_type AS TEXT), CAST(is_nullable AS TEXT), CAST(character_maximum_length AS BIGINT), CAST(column_default AS TEXT), CAST(is_identity AS TEXT) FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = {} ORDER BY ordinal_position;",
+                        quote(&name)
+                    ),
+                    log_sql
+                ),
+                Vec::new(),
+            )
+            .await?;
+        for row in rows {
+            let column_name: String = row.get(0)?;
+            let data_type: String = row.get(1)?;
+            let nullable: String = row.get(2)?;
+            let max_length: Option<i64> = row.get(3)?;
+            let default: Option<String> = row.get(4)?;
+            let identity: String = row.get(5)?;
+            // Serial columns use a sequence as default
+            let auto_increment =
+                identity == "YES" || default.is_some_and(|x| x.starts_with("nextval("));
+
+            columns.push(DbColumn {
+                name: column_name,
+                data_type,
+                max_length,
+                nullable: nullable == "YES",
+                primary_key: false,
+                unique: false,
+                auto_increment,
+                foreign_key: None,
+            });
+        }
+
+        let mut keys = KeyConstraints::new();
+        let mut foreign_keys = vec![];
+        let rows = db
+            .execute::<All>(
+                log_sql!(
+                    format!(
+                        "SELECT CAST(tc.constraint_name AS TEXT), CAST(tc.constraint_type AS TEXT), CAST(kcu.column_name AS TEXT), CAST(ccu.table_name AS TEXT), CAST(ccu.column_name AS TEXT) \
+                        FROM information_schema.table_constraints AS tc \
+                        JOIN information_schema.key_column_usage AS kcu ON tc.constraint_name = kcu.constraint_name AND tc.table_schema = kcu.table_schema \
+                        JOIN information_schema.constraint_column_usage AS ccu ON tc.constraint_name = ccu.constraint_name AND tc.table_schema = ccu.table_schema \
+                        WHERE tc.table_schema = current_schema() AND tc.table_name = {} AND tc.constraint_type IN ('PRIMARY KEY', 'UNIQUE', 'FOREIGN KEY');",
+                        quote(&name)
+                    ),
+                    log_sql
+                ),
+                Vec::new(),
+            )
+            .await?;
+        for row in rows {
+            let constraint_name: String = row.get(0)?;
+            let constraint_type: String = row.get(1)?;
+            let column_name: String = row.get(2)?;
+            let table: String = row.get(3)?;
+            let referenced: String = row.get(4)?;
+
+            if constraint_type == "FOREIGN KEY" {
+                foreign_keys.push((column_name, table, referenced));
+            } else {
+                let key = keys
+                    .entry(constraint_name)
+                    .or_insert((constraint_type == "PRIMARY KEY", vec![]));
+                // The join with the referenced columns duplicates multi column constraints
+                if !key.1.contains(&column_name) {
+                    key.1.push(column_name);
+                }
+            }
+        }
+
+        apply_constraints(&mut columns, keys, foreign_keys);
+        tables.push(DbTable { name, columns });
+    }
+
+    Ok(tables)
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fs::{read_dir, read_to_string, DirEntry, File};
 use std::io::Write;
 use std::path::Path;
@@ -5,8 +6,10 @@ use std::path::Path;
 use anyhow::{anyhow, Context};
 use rorm_declaration::imr::{InternalModelFormat, Model};
 use rorm_declaration::migration::{Migration, MigrationFile, Operation};
+use sha2::{Digest, Sha256};
 
 use crate::utils::re::RE;
+use crate::{print_message, print_warning};
 
 /**
 This function is used to convert the [InternalModelFormat] into its TOML representation.
@@ -55,6 +58,189 @@ pub fn convert_file_to_migration(path: &DirEntry) -> anyhow::Result<MigrationFil
     Ok(migration)
 }
 
+/// Name of the file in the migration directory the checksums of the migrations are recorded in
+const CHECKSUM_FILE: &str = ".checksums.json";
+
+/**
+Helper function to calculate the checksum of a migration.
+
+Only the operations are taken into account, so formatting the file does not change the checksum.
+
+`migration`: [&Migration]: The migration to calculate the checksum of.
+ */
+pub fn migration_checksum(migration: &Migration) -> anyhow::Result<String> {
+    let operations = serde_json::to_string(&migration.operations)
+        .with_context(|| "Error while serializing operations")?;
+
+    Ok(format!("{:x}", Sha256::digest(operations)))
+}
+
+fn read_checksum_file(migration_dir: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    let path = Path::new(migration_dir).join(CHECKSUM_FILE);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let content = read_to_string(&path)
+        .with_context(|| format!("Error occurred while reading {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Error while deserializing {}", path.display()))
+}
+
+fn write_checksum_file(
+    migration_dir: &str,
+    checksums: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let path = Path::new(migration_dir).join(CHECKSUM_FILE);
+    let content = serde_json::to_string_pretty(checksums)
+        .with_context(|| "Error while serializing checksums")?;
+
+    let mut output = File::create(&path).with_context(|| {
+        format!(
+            "Error while opening file {} to write checksums into",
+            path.display()
+        )
+    })?;
+    writeln!(output, "{content}").with_context(|| "Error while writing to checksum file")?;
+
+    Ok(())
+}
+
+/**
+Helper function to record the checksum of a migration in the migration directory.
+
+`migration_dir`: [&str]: The directory the migration is located in.
+`migration`: [&Migration]: The migration to record the checksum of.
+ */
+pub fn record_checksum(migration_dir: &str, migration: &Migration) -> anyhow::Result<()> {
+    let mut checksums = read_checksum_file(migration_dir)?;
+    checksums.insert(
+        format!("{:04}_{}", migration.id, migration.name),
+        migration_checksum(migration)?,
+    );
+    write_checksum_file(migration_dir, &checksums)
+}
+
+/**
+Helper function to compare migrations with the checksums recorded in the migration directory.
+
+Migrations without a recorded checksum are recorded with a warning, as changes
+made to them before can't be detected.
+Changed migrations are an error, unless their id is listed in `accept_changed`.
+In this case the recorded checksum is updated.
+
+`migration_dir`: [&str]: The directory the migrations are located in.
+`migrations`: [&[Migration]]: The migrations to verify.
+`accept_changed`: [&[u16]]: Ids of migrations whose changes are accepted.
+`dry_run`: [bool]: If set, the recorded checksums are not updated.
+ */
+pub fn verify_recorded_checksums(
+    migration_dir: &str,
+    migrations: &[Migration],
+    accept_changed: &[u16],
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let recorded = read_checksum_file(migration_dir)?;
+    let mut checksums = recorded.clone();
+    let mut unrecorded = vec![];
+
+    for migration in migrations {
+        let key = format!("{:04}_{}", migration.id, migration.name);
+        let checksum = migration_checksum(migration)?;
+
+        match checksums.get(&key) {
+            Some(x) if *x != checksum => {
+                if !accept_changed.contains(&migration.id) {
+                    return Err(anyhow!(
+                        "Migration {key} was changed since it has been created.\n\n\
+                        Revert the changes or accept them with --accept-changed {}.",
+                        migration.id
+                    ));
+                }
+                print_message!("Accepted changes of migration {key}");
+            }
+            Some(_) => continue,
+            None => unrecorded.push(key.clone()),
+        }
+
+        checksums.insert(key, checksum);
+    }
+
+    if !unrecorded.is_empty() {
+        print_warning!(
+            "Migrations {} had no recorded checksum. Changes made to them before can't be detected, \
+            make sure they match the migrations that have been applied.",
+            unrecorded.join(", ")
+        );
+    }
+
+    if checksums != recorded && !dry_run {
+        write_checksum_file(migration_dir, &checksums)?;
+    }
+
+    Ok(())
+}
+
+/**
+Helper function to check whether a migration must be applied in a transaction.
+
+Migrations opt out by setting `Atomic = false` in the `[Migration]` table of their file.
+Their operations are applied one by one and the progress is recorded after each operation.
+
+`migration_dir`: [&str]: The directory the migration is located in.
+`migration`: [&Migration]: The migration to check.
+ */
+pub fn is_atomic(migration_dir: &str, migration: &Migration) -> anyhow::Result<bool> {
+    let path =
+        Path::new(migration_dir).join(format!("{:04}_{}.toml", migration.id, migration.name));
+    let toml_str = read_to_string(&path)
+        .with_context(|| format!("Error occurred while reading {}", path.display()))?;
+    let value: toml::Value = toml::from_str(toml_str.as_str())
+        .with_context(|| format!("Error while deserializing {}", path.display()))?;
+
+    match value.get("Migration").and_then(|x| x.get("Atomic")) {
+        None => Ok(true),
+        Some(atomic) => atomic.as_bool().ok_or_else(|| {
+            anyhow!(
+                "Atomic must be a boolean in migration file {}",
+                path.display()
+            )
+        }),
+    }
+}
+
+/**
+Helper function to opt a written migration out of transactions, see [is_atomic].
+
+Sets `Atomic = false` in the `[Migration]` table of the file.
+
+`path`: [&Path]: The path of the migration file.
+ */
+pub fn mark_non_atomic(path: &Path) -> anyhow::Result<()> {
+    let toml_str = read_to_string(path)
+        .with_context(|| format!("Error occurred while reading {}", path.display()))?;
+    let mut value: toml::Value = toml::from_str(toml_str.as_str())
+        .with_context(|| format!("Error while deserializing {}", path.display()))?;
+
+    value
+        .get_mut("Migration")
+        .and_then(|x| x.as_table_mut())
+        .ok_or_else(|| anyhow!("Missing [Migration] table in {}", path.display()))?
+        .insert("Atomic".to_string(), toml::Value::Boolean(false));
+
+    let toml_str =
+        toml::to_string_pretty(&value).with_context(|| "Error while serializing migration")?;
+    let mut output = File::create(path).with_context(|| {
+        format!(
+            "Error while opening file {:?} to write migration into",
+            path.file_name()
+        )
+    })?;
+    write!(output, "{toml_str}").with_context(|| "Error while writing to migration file")?;
+
+    Ok(())
+}
+
 pub(crate) fn get_migration_files(migration_dir: &str) -> anyhow::Result<Vec<DirEntry>> {
     let dir_entries =
         read_dir(migration_dir).with_context(|| "Error while searching the migration directory")?;
@@ -91,10 +277,16 @@ pub fn get_existing_migrations(migration_dir: &str) -> anyhow::Result<Vec<Migrat
 
     let mut migration_list: Vec<Migration> = vec![];
 
-    // Filter out migrations that replace migrations
-    for m in migrations {
-        if m.replaces.is_empty() {
-            migration_list.push(m);
+    // Filter out migrations that replace migrations, as long as the replaced ones exist
+    for m in &migrations {
+        if m.replaces.is_empty()
+            || !m.replaces.iter().any(|x| {
+                migrations
+                    .iter()
+                    .any(|y| y.replaces.is_empty() && y.id == *x)
+            })
+        {
+            migration_list.push(m.clone());
         }
     }
 
@@ -160,104 +352,180 @@ pub fn get_all_existing_migrations(migration_dir: &str) -> anyhow::Result<Vec<Mi
 }
 
 /**
-Helper function to converts a list of migrations to an internal model.
+Helper function to replace squashed migrations by the migration replacing them.
 
-`migrations`: [Vec<Migration>]: List of migrations
- */
-pub fn convert_migrations_to_internal_models(
-    migrations: &[Migration],
-) -> anyhow::Result<InternalModelFormat> {
-    let mut m = vec![];
+A run of migrations is only replaced if none of its migrations has been applied yet and
+applying should not stop in the middle of the run.
 
-    let res: anyhow::Result<_> = migrations.iter().try_for_each(|x| {
-        x.operations.iter().try_for_each(|y| match y {
-            Operation::CreateModel { name, fields } => {
-                m.push(Model {
-                    name: name.clone(),
-                    fields: fields.clone(),
-                    source_defined_at: None,
-                });
-                Ok(())
-            }
-            Operation::RenameModel { old, new } => {
-                m = m
-                    .iter()
-                    .map(|z| {
-                        let mut a = z.clone();
-                        if &a.name == old {
-                            a.name = new.to_string();
-                        }
-                        a
-                    })
-                    .collect();
-                Ok(())
-            }
-            Operation::DeleteModel { name } => {
-                m.retain(|z| z.name != *name);
-                Ok(())
+**Parameter**:
+- `migration_dir`: [&str] The directory to search for squashed migrations.
+- `migrations`: [Vec<Migration>] Sorted list of migrations, see [get_existing_migrations].
+- `last_applied`: [Option<u16>] The id of the last applied migration.
+- `apply_until`: [Option<u16>] The id of the last migration that should be applied.
+*/
+pub fn substitute_squashed_migrations(
+    migration_dir: &str,
+    mut migrations: Vec<Migration>,
+    last_applied: Option<u16>,
+    apply_until: Option<u16>,
+) -> anyhow::Result<Vec<Migration>> {
+    let squashed: Vec<Migration> = get_all_existing_migrations(migration_dir)?
+        .into_iter()
+        .filter(|x| !x.replaces.is_empty())
+        .collect();
+
+    for squash in squashed {
+        let positions: Vec<usize> = squash
+            .replaces
+            .iter()
+            .filter_map(|x| migrations.iter().position(|y| y.id == *x))
+            .collect();
+
+        let (Some(&start), Some(&end)) = (positions.iter().min(), positions.iter().max()) else {
+            continue;
+        };
+
+        // The replaced migrations must form a coherent run that ends with the squashed id
+        if positions.len() != squash.replaces.len()
+            || end - start + 1 != positions.len()
+            || migrations[end].id != squash.id
+        {
+            continue;
+        }
+
+        let applied = last_applied
+            .and_then(|x| migrations.iter().position(|y| y.id == x))
+            .is_some_and(|x| x >= start);
+        let stops_inside = apply_until
+            .and_then(|x| migrations.iter().position(|y| y.id == x))
+            .is_some_and(|x| x >= start && x < end);
+
+        if !applied && !stops_inside {
+            let rest = migrations.split_off(end + 1);
+            migrations.truncate(start);
+            migrations.push(squash);
+            migrations.extend(rest);
+        }
+    }
+
+    Ok(migrations)
+}
+
+/**
+Helper function to apply an operation to a list of models.
+
+`models`: [&mut Vec<Model>]: List of models the operation is applied to
+`operation`: [&Operation]: The operation to apply
+ */
+pub fn apply_operation(models: &mut Vec<Model>, operation: &Operation) -> anyhow::Result<()> {
+    match operation {
+        Operation::CreateModel { name, fields } => {
+            models.push(Model {
+                name: name.clone(),
+                fields: fields.clone(),
+                source_defined_at: None,
+            });
+        }
+        Operation::RenameModel { old, new } => {
+            for model in models.iter_mut() {
+                if &model.name == old {
+                    model.name = new.to_string();
+                }
             }
-            Operation::CreateField { model, field } => {
-                for i in &mut m {
-                    if i.name == *model {
-                        i.fields.push(field.clone());
-                    }
+        }
+        Operation::DeleteModel { name } => {
+            models.retain(|z| z.name != *name);
+        }
+        Operation::CreateField { model, field } => {
+            for i in models.iter_mut() {
+                if i.name == *model {
+                    i.fields.push(field.clone());
                 }
-                Ok(())
             }
-            Operation::RenameField {
-                table_name,
-                old,
-                new,
-            } => {
-                m = m
-                    .iter()
-                    .map(|z| {
-                        let mut a = z.clone();
-                        if &a.name == table_name {
-                            a.fields = a
-                                .fields
-                                .iter()
-                                .map(|b| {
-                                    let mut c = b.clone();
-                                    if &c.name == old {
-                                        c.name = new.to_string();
-                                    }
-                                    c
-                                })
-                                .collect();
+        }
+        Operation::RenameField {
+            table_name,
+            old,
+            new,
+        } => {
+            for model in models.iter_mut() {
+                if &model.name == table_name {
+                    for field in model.fields.iter_mut() {
+                        if &field.name == old {
+                            field.name = new.to_string();
                         }
-                        a
-                    })
-                    .collect();
-                Ok(())
-            }
-            Operation::DeleteField { model, name } => {
-                for i in &mut m {
-                    if i.name == *model {
-                        i.fields.retain(|z| z.name != *name);
                     }
                 }
-                Ok(())
             }
-            Operation::RawSQL { structure_safe, .. } => {
-                if *structure_safe {
-                    Ok(())
-                } else {
-                    Err(anyhow!(
-                        r#"RawSQL migration without StructureSafe flag found!
+        }
+        Operation::DeleteField { model, name } => {
+            for i in models.iter_mut() {
+                if i.name == *model {
+                    i.fields.retain(|z| z.name != *name);
+                }
+            }
+        }
+        Operation::RawSQL { structure_safe, .. } => {
+            if !*structure_safe {
+                return Err(anyhow!(
+                    r#"RawSQL migration without StructureSafe flag found!
 
 Can not proceed to generate migrations as the current database state can not be determined anymore!
 You can still write migrations with all available operations yourself.
 
 To use the make-migrations feature again, check that all RawSQL operations don't change any 
 structure and mark them as StructureSafe or delete all RawSQL operations."#
-                    ))
-                }
+                ));
             }
-        })
-    });
+        }
+    }
+
+    Ok(())
+}
+
+/**
+Helper function to apply an operation to a known state of the models.
+
+The state becomes unknown, if the operation can't be applied to it.
+
+`models`: [&mut Option<Vec<Model>>]: State of the models, if known
+`operation`: [&Operation]: The operation to apply
+ */
+pub fn apply_operation_to_state(models: &mut Option<Vec<Model>>, operation: &Operation) {
+    if let Some(state) = models {
+        if apply_operation(state, operation).is_err() {
+            *models = None;
+        }
+    }
+}
+
+/**
+Helper function to apply all operations of a migration to a known state of the models.
+
+`models`: [&mut Option<Vec<Model>>]: State of the models, if known
+`migration`: [&Migration]: The migration to apply
+ */
+pub fn apply_migration_to_state(models: &mut Option<Vec<Model>>, migration: &Migration) {
+    for operation in &migration.operations {
+        apply_operation_to_state(models, operation);
+    }
+}
 
-    res?;
+/**
+Helper function to converts a list of migrations to an internal model.
+
+`migrations`: [Vec<Migration>]: List of migrations
+ */
+pub fn convert_migrations_to_internal_models(
+    migrations: &[Migration],
+) -> anyhow::Result<InternalModelFormat> {
+    let mut m = vec![];
+
+    for migration in migrations {
+        for operation in &migration.operations {
+            apply_operation(&mut m, operation)?;
+        }
+    }
 
     Ok(InternalModelFormat { models: m })
 }
@@ -266,10 +534,13 @@ structure and mark them as StructureSafe or delete all RawSQL operations."#
 mod test {
     use std::path::Path;
 
-    use rorm_declaration::migration::Migration;
+    use rorm_declaration::migration::{Migration, Operation};
     use temp_dir::TempDir;
 
-    use crate::utils::migrations::{convert_migration_to_file, get_existing_migrations};
+    use crate::utils::migrations::{
+        convert_migration_to_file, get_existing_migrations, is_atomic, mark_non_atomic,
+        record_checksum, verify_recorded_checksums,
+    };
 
     #[test]
     fn test_get_existing_migrations_non_initial() {
@@ -347,4 +618,62 @@ mod test {
 
         assert!(get_existing_migrations(tmp.path().to_str().unwrap()).is_ok());
     }
+
+    #[test]
+    fn test_mark_non_atomic() {
+        let tmp = TempDir::new().expect("Could not create a temporary directory");
+        let migration_dir = tmp.path().to_str().unwrap();
+        let p = tmp.path().join("0001_initial.toml");
+
+        let migration = Migration {
+            hash: "".to_string(),
+            initial: true,
+            id: 1,
+            name: "initial".to_string(),
+            dependency: None,
+            replaces: vec![],
+            operations: vec![],
+        };
+
+        convert_migration_to_file(migration.clone(), &p).expect("Could not write to file");
+        assert!(is_atomic(migration_dir, &migration).unwrap());
+
+        mark_non_atomic(&p).expect("Could not mark migration as non-atomic");
+        assert!(!is_atomic(migration_dir, &migration).unwrap());
+        assert!(get_existing_migrations(migration_dir).is_ok());
+    }
+
+    #[test]
+    fn test_verify_recorded_checksums() {
+        let tmp = TempDir::new().expect("Could not create a temporary directory");
+        let migration_dir = tmp.path().to_str().unwrap();
+
+        let mut migration = Migration {
+            hash: "".to_string(),
+            initial: true,
+            id: 1,
+            name: "initial".to_string(),
+            dependency: None,
+            replaces: vec![],
+            operations: vec![],
+        };
+
+        record_checksum(migration_dir, &migration).expect("Could not record checksum");
+        assert!(verify_recorded_checksums(migration_dir, &[migration.clone()], &[], false).is_ok());
+
+        // Changing the hash is no change of the operations
+        migration.hash = "1234".to_string();
+        assert!(verify_recorded_checksums(migration_dir, &[migration.clone()], &[], false).is_ok());
+
+        migration.operations.push(Operation::DeleteModel {
+            name: "foo".to_string(),
+        });
+        assert!(
+            verify_recorded_checksums(migration_dir, &[migration.clone()], &[], false).is_err()
+        );
+        assert!(
+            verify_recorded_checksums(migration_dir, &[migration.clone()], &[1], false).is_ok()
+        );
+        assert!(verify_recorded_checksums(migration_dir, &[migration], &[], false).is_ok());
+    }
 }
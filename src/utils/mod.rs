@@ -1,7 +1,11 @@
 use std::io;
 use std::io::Write;
 
+use crate::utils::output::{output_format, OutputFormat};
+
+pub mod introspection;
 pub mod migrations;
+pub mod output;
 pub mod re;
 
 #[macro_export]
@@ -9,7 +13,7 @@ macro_rules! log_sql {
     ($query:expr, $do_log:expr) => {{
         let log_sql_q: String = $query;
         if $do_log {
-            println!("{}", log_sql_q);
+            $crate::utils::output::print_sql(&log_sql_q);
         }
         log_sql_q
     }};
@@ -17,8 +21,14 @@ macro_rules! log_sql {
 
 pub(crate) fn question(question: &str) -> bool {
     loop {
-        print!("{question} [yN] ");
-        io::stdout().flush().expect("Flushing stdout should work!");
+        // Questions must not interfere with the events written to stdout
+        if output_format() == OutputFormat::Json {
+            eprint!("{question} [yN] ");
+            io::stderr().flush().expect("Flushing stderr should work!");
+        } else {
+            print!("{question} [yN] ");
+            io::stdout().flush().expect("Flushing stdout should work!");
+        }
         let mut input = String::new();
         match io::stdin().read_line(&mut input) {
             Ok(_) => {
@@ -30,7 +40,7 @@ pub(crate) fn question(question: &str) -> bool {
                 }
             }
             Err(error) => {
-                println!("error: {error}");
+                eprintln!("error: {error}");
                 return false;
             }
         }
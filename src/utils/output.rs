@@ -0,0 +1,278 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+/// Format of the output of all commands
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable messages
+    #[default]
+    Text,
+    /// One JSON object per line for every event
+    Json,
+}
+
+static OUTPUT_FORMAT: OnceCell<OutputFormat> = OnceCell::new();
+static CHANGES_DETECTED: AtomicBool = AtomicBool::new(false);
+
+/// Sets the format of the output, only the first call has an effect
+pub fn set_output_format(format: OutputFormat) {
+    let _ = OUTPUT_FORMAT.set(format);
+}
+
+/// Retrieves the format of the output
+pub fn output_format() -> OutputFormat {
+    OUTPUT_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Checks whether any event reported a change, see [Event::is_change]
+pub fn changes_detected() -> bool {
+    CHANGES_DETECTED.load(Ordering::Relaxed)
+}
+
+/// Coarse classification of errors
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Reading or writing files failed
+    Io,
+    /// A file could not be parsed
+    Parse,
+    /// The database reported an error
+    Database,
+    /// Changes were detected, but none were expected, see [UnexpectedChanges]
+    UnexpectedChanges,
+    /// Any other error, for example an inconsistent migration history
+    Other,
+}
+
+impl ErrorKind {
+    /// Classifies an error by the first known error in its chain of causes
+    pub fn of_error(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if cause.is::<std::io::Error>() {
+                return Self::Io;
+            }
+            if cause.is::<toml::de::Error>() || cause.is::<serde_json::Error>() {
+                return Self::Parse;
+            }
+            if cause.is::<rorm_db::Error>() {
+                return Self::Database;
+            }
+            if cause.is::<UnexpectedChanges>() {
+                return Self::UnexpectedChanges;
+            }
+        }
+
+        Self::Other
+    }
+}
+
+/// Error returned by commands that only check for changes, if changes were detected
+///
+/// It is reported with the exit code for changes instead of the one for failures.
+#[derive(Debug)]
+pub struct UnexpectedChanges(pub String);
+
+impl Display for UnexpectedChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UnexpectedChanges {}
+
+/// Events reported by the commands
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// Informational message without structured data
+    Message {
+        message: &'a str,
+    },
+    /// Warning that does not abort the command
+    Warning {
+        message: &'a str,
+    },
+    /// Command finished without changing anything
+    NothingToDo {
+        message: &'a str,
+    },
+    /// Logged SQL statement
+    Sql {
+        query: &'a str,
+    },
+    /// SQL statements of a migration, printed instead of applied
+    MigrationSql {
+        id: u16,
+        name: &'a str,
+        statements: Vec<&'a str>,
+    },
+    /// Policy for detected renames, see make-migrations
+    RenamePolicy {
+        policy: &'a str,
+    },
+    ModelCreated {
+        model: &'a str,
+    },
+    ModelDeleted {
+        model: &'a str,
+    },
+    ModelRenamed {
+        old: &'a str,
+        new: &'a str,
+    },
+    FieldCreated {
+        model: &'a str,
+        field: &'a str,
+    },
+    FieldDeleted {
+        model: &'a str,
+        field: &'a str,
+    },
+    FieldRenamed {
+        model: &'a str,
+        old: &'a str,
+        new: &'a str,
+    },
+    FieldRecreated {
+        model: &'a str,
+        field: &'a str,
+    },
+    FileWritten {
+        path: &'a str,
+    },
+    FileRemoved {
+        path: &'a str,
+    },
+    MigrationApplied {
+        id: u16,
+        name: &'a str,
+        duration_ms: i64,
+    },
+    MigrationReverted {
+        id: u16,
+        name: &'a str,
+    },
+    /// Migration recorded as applied without running it
+    MigrationRecorded {
+        id: u16,
+        name: &'a str,
+    },
+    /// Record of a migration removed without reverting it
+    MigrationUnrecorded {
+        id: u16,
+    },
+    /// State of a migration, see show-migrations
+    MigrationStatus {
+        id: u16,
+        name: Option<&'a str>,
+        status: &'a str,
+        applied_at: Option<&'a str>,
+        applied_operations: Option<u16>,
+    },
+    /// Difference between the database and the migrations, see check-db
+    SchemaDifference {
+        message: &'a str,
+    },
+    Error {
+        kind: ErrorKind,
+        message: &'a str,
+    },
+}
+
+impl Event<'_> {
+    /// Checks whether the event describes a change of the migrations or the database
+    pub fn is_change(&self) -> bool {
+        matches!(
+            self,
+            Self::ModelCreated { .. }
+                | Self::ModelDeleted { .. }
+                | Self::ModelRenamed { .. }
+                | Self::FieldCreated { .. }
+                | Self::FieldDeleted { .. }
+                | Self::FieldRenamed { .. }
+                | Self::FieldRecreated { .. }
+                | Self::FileWritten { .. }
+                | Self::FileRemoved { .. }
+                | Self::MigrationApplied { .. }
+                | Self::MigrationReverted { .. }
+                | Self::MigrationRecorded { .. }
+                | Self::MigrationUnrecorded { .. }
+        )
+    }
+}
+
+/**
+Helper function to report an event.
+
+The text is printed in the text format, the serialized event in the JSON format.
+
+`event`: [Event]: The event to report.
+`text`: [impl Display]: Human readable description of the event.
+ */
+pub fn report(event: Event, text: impl Display) {
+    if event.is_change() {
+        CHANGES_DETECTED.store(true, Ordering::Relaxed);
+    }
+
+    match output_format() {
+        OutputFormat::Text => println!("{text}"),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string(&event).expect("Serializing events should work!")
+        ),
+    }
+}
+
+/**
+Helper function to report a logged SQL statement.
+
+`query`: [&str]: The SQL statement.
+ */
+pub fn print_sql(query: &str) {
+    report(Event::Sql { query }, query);
+}
+
+/**
+Helper function to report an error that aborted a command.
+
+`error`: [&anyhow::Error]: The error to report.
+ */
+pub fn report_error(error: &anyhow::Error) {
+    let message = format!("{error:#}");
+    report(
+        Event::Error {
+            kind: ErrorKind::of_error(error),
+            message: &message,
+        },
+        format!("Error: {error:?}"),
+    );
+}
+
+/// Reports an informational message, see [report]
+#[macro_export]
+macro_rules! print_message {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        $crate::utils::output::report(
+            $crate::utils::output::Event::Message { message: &message },
+            &message,
+        );
+    }};
+}
+
+/// Reports a warning, see [report]
+#[macro_export]
+macro_rules! print_warning {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        $crate::utils::output::report(
+            $crate::utils::output::Event::Warning { message: &message },
+            format!("Warning: {message}"),
+        );
+    }};
+}
@@ -6,6 +6,7 @@ pub struct Regexes {
     pub forbidden_character: Regex,
     pub migration_allowed_comment: Regex,
     pub migration_allowed_name: Regex,
+    pub env_variable: Regex,
 }
 
 pub static RE: Lazy<Regexes> = Lazy::new(|| Regexes {
@@ -13,4 +14,5 @@ pub static RE: Lazy<Regexes> = Lazy::new(|| Regexes {
     forbidden_character: Regex::new(r#"[^a-zA-Z0-9_]"#).unwrap(),
     migration_allowed_comment: Regex::new(r"^\w+$").unwrap(),
     migration_allowed_name: Regex::new(r"^[0-9]{4}_\w+\.toml$").unwrap(),
+    env_variable: Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").unwrap(),
 });